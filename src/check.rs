@@ -0,0 +1,174 @@
+//! Typechecking of gluon files outside of an editor, reporting the same diagnostics as the
+//! language server
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use url::Url;
+
+use gluon::new_vm;
+
+use languageserver_types::{Diagnostic, PublishDiagnosticsParams};
+
+use super::{CheckImport, PositionEncoding, create_diagnostics, glu_files_in, read_file,
+            typecheck};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// Errors with source snippets in the style of rustc
+    Human,
+    /// A JSON array of `textDocument/publishDiagnostics` parameters
+    Json,
+}
+
+/// Typechecks the files in `paths`, searching directories for gluon files, and writes the
+/// diagnostics to `output`. Returns `true` if any errors were found.
+pub fn check<W>(mut output: W, paths: &[PathBuf], format: Format) -> io::Result<bool>
+where
+    W: Write,
+{
+    let thread = new_vm();
+    let import = CheckImport::new();
+    thread.get_macros().insert("import".into(), import);
+
+    let mut diagnostics = BTreeMap::<Url, Vec<Diagnostic>>::new();
+    for file in glu_files_in(paths) {
+        let path = try!(fs::canonicalize(&file));
+        let uri = try!(Url::from_file_path(&path).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unable to convert `{}` to a url", path.display()),
+            )
+        }));
+        let mut source = String::new();
+        try!(fs::File::open(&path).and_then(|mut file| {
+            io::Read::read_to_string(&mut file, &mut source)
+        }));
+
+        let mut file_diagnostics = BTreeMap::new();
+        if let Err(err) = typecheck(&thread, &uri, &source) {
+            create_diagnostics(
+                &thread,
+                PositionEncoding::Utf16,
+                &mut file_diagnostics,
+                &uri,
+                &source,
+                err,
+            );
+        }
+        // Errors in an imported module are reported when checking each module that imports it
+        for (uri, new_diagnostics) in file_diagnostics {
+            let uri_diagnostics = diagnostics.entry(uri).or_insert_with(Vec::new);
+            for diagnostic in new_diagnostics {
+                if !uri_diagnostics.contains(&diagnostic) {
+                    uri_diagnostics.push(diagnostic);
+                }
+            }
+        }
+    }
+
+    let error_count = diagnostics.values().map(|diagnostics| diagnostics.len()).sum::<usize>();
+    match format {
+        Format::Human => {
+            for (uri, diagnostics) in &diagnostics {
+                let source = read_file(uri).unwrap_or_default();
+                for diagnostic in diagnostics {
+                    try!(write_diagnostic(&mut output, uri, &source, diagnostic));
+                }
+            }
+            if error_count != 0 {
+                try!(writeln!(
+                    output,
+                    "error: found {} error{}",
+                    error_count,
+                    if error_count == 1 { "" } else { "s" }
+                ));
+            }
+        }
+        Format::Json => {
+            let diagnostics: Vec<_> = diagnostics
+                .into_iter()
+                .filter(|&(_, ref diagnostics)| !diagnostics.is_empty())
+                .map(|(uri, diagnostics)| {
+                    PublishDiagnosticsParams {
+                        uri: uri,
+                        diagnostics: diagnostics,
+                    }
+                })
+                .collect();
+            try!(::serde_json::to_writer(&mut output, &diagnostics).map_err(|err| {
+                io::Error::new(io::ErrorKind::Other, err)
+            }));
+            try!(writeln!(output));
+        }
+    }
+    Ok(error_count != 0)
+}
+
+fn write_diagnostic<W>(
+    output: &mut W,
+    uri: &Url,
+    source: &str,
+    diagnostic: &Diagnostic,
+) -> io::Result<()>
+where
+    W: Write,
+{
+    let path = uri.to_file_path().unwrap_or_else(|_| PathBuf::from(uri.as_str()));
+    let path = env::current_dir()
+        .ok()
+        .and_then(|dir| path.strip_prefix(dir).ok().map(|path| path.to_owned()))
+        .unwrap_or(path);
+    let start = &diagnostic.range.start;
+    let end = &diagnostic.range.end;
+    let line = source.lines().nth(start.line as usize);
+    let start_column = line.map_or(start.character as usize, |line| {
+        char_column(line, start.character)
+    });
+
+    let line_number = (start.line + 1).to_string();
+    let gutter = " ".repeat(line_number.len());
+
+    try!(writeln!(output, "error: {}", diagnostic.message));
+    try!(writeln!(
+        output,
+        "{}--> {}:{}:{}",
+        gutter,
+        path.display(),
+        line_number,
+        start_column + 1
+    ));
+    if let Some(line) = line {
+        let end_column = if end.line == start.line {
+            char_column(line, end.character)
+        } else {
+            line.chars().count()
+        };
+        let marker_len = end_column.saturating_sub(start_column).max(1);
+        try!(writeln!(output, "{} |", gutter));
+        try!(writeln!(output, "{} | {}", line_number, line));
+        try!(writeln!(
+            output,
+            "{} | {}{}",
+            gutter,
+            " ".repeat(start_column),
+            "^".repeat(marker_len)
+        ));
+    }
+    writeln!(output)
+}
+
+/// Returns the number of characters in `line` before `character`, which counts UTF-16 code units
+/// as positions in diagnostics do
+fn char_column(line: &str, character: u64) -> usize {
+    let mut offset = 0;
+    line.chars()
+        .take_while(|c| {
+            offset += c.len_utf16() as u64;
+            offset <= character
+        })
+        .count()
+}
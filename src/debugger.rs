@@ -29,7 +29,7 @@ use std::collections::{HashMap, HashSet};
 use clap::{App, Arg};
 use futures::{Async, BoxFuture, Future, IntoFuture};
 
-use jsonrpc_core::IoHandler;
+use jsonrpc_core::{ErrorCode, IoHandler};
 
 use serde_json::Value;
 
@@ -100,6 +100,7 @@ impl LaunchHandler {
         let program = args.get("program").and_then(|s| s.as_str()).ok_or_else(
             || {
                 ServerError {
+                    code: ErrorCode::InternalError,
                     message: "No program argument found".into(),
                     data: None,
                 }
@@ -110,6 +111,7 @@ impl LaunchHandler {
         let expr = {
             let mut file = File::open(&*program).map_err(|_| {
                 ServerError {
+                    code: ErrorCode::InternalError,
                     message: format!("Program does not exist: `{}`", program),
                     data: None,
                 }
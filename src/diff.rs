@@ -0,0 +1,142 @@
+//! Line based diffs between two versions of a text
+
+use std::ops::Range;
+
+/// The largest number of line pairs which are compared when searching for the smallest diff.
+/// Larger changes are reported as a single hunk.
+const MAX_COMPARISONS: usize = 4_000_000;
+
+/// The lines `old` of the original text are replaced by the lines `new` of the changed text
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hunk {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+/// Splits `text` into lines, keeping the line endings so that the lines join back into `text`
+pub fn lines(text: &str) -> Vec<&str> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for (i, _) in text.match_indices('\n') {
+        lines.push(&text[start..i + 1]);
+        start = i + 1;
+    }
+    if start != text.len() {
+        lines.push(&text[start..]);
+    }
+    lines
+}
+
+/// Returns the hunks, in order, which turn the lines `old` into the lines `new`
+pub fn diff_lines(old: &[&str], new: &[&str]) -> Vec<Hunk> {
+    let prefix = old.iter().zip(new).take_while(|&(x, y)| x == y).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|&(x, y)| x == y)
+        .count();
+    let old_changed = &old[prefix..old.len() - suffix];
+    let new_changed = &new[prefix..new.len() - suffix];
+
+    if old_changed.is_empty() && new_changed.is_empty() {
+        return Vec::new();
+    }
+    if old_changed.is_empty() || new_changed.is_empty() ||
+        old_changed.len() * new_changed.len() > MAX_COMPARISONS
+    {
+        return vec![
+            Hunk {
+                old: prefix..prefix + old_changed.len(),
+                new: prefix..prefix + new_changed.len(),
+            },
+        ];
+    }
+
+    // `common[i][j]` is the length of the longest common subsequence of `old_changed[i..]` and
+    // `new_changed[j..]`
+    let width = new_changed.len() + 1;
+    let mut common = vec![0u32; (old_changed.len() + 1) * width];
+    for i in (0..old_changed.len()).rev() {
+        for j in (0..new_changed.len()).rev() {
+            common[i * width + j] = if old_changed[i] == new_changed[j] {
+                common[(i + 1) * width + j + 1] + 1
+            } else {
+                common[(i + 1) * width + j].max(common[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut hunks = Vec::new();
+    let mut hunk_start = None;
+    let (mut i, mut j) = (0, 0);
+    while i < old_changed.len() || j < new_changed.len() {
+        if i < old_changed.len() && j < new_changed.len() && old_changed[i] == new_changed[j] {
+            if let Some((old_start, new_start)) = hunk_start.take() {
+                hunks.push(Hunk {
+                    old: prefix + old_start..prefix + i,
+                    new: prefix + new_start..prefix + j,
+                });
+            }
+            i += 1;
+            j += 1;
+            continue;
+        }
+        if hunk_start.is_none() {
+            hunk_start = Some((i, j));
+        }
+        if j == new_changed.len() ||
+            (i < old_changed.len() && common[(i + 1) * width + j] >= common[i * width + j + 1])
+        {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    if let Some((old_start, new_start)) = hunk_start {
+        hunks.push(Hunk {
+            old: prefix + old_start..prefix + i,
+            new: prefix + new_start..prefix + j,
+        });
+    }
+    hunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_lines_keeps_line_endings() {
+        assert_eq!(lines("a\nb\r\nc"), ["a\n", "b\r\n", "c"]);
+        assert_eq!(lines("a\n"), ["a\n"]);
+        assert!(lines("").is_empty());
+    }
+
+    #[test]
+    fn diff_changed_lines() {
+        let old = ["a", "b", "c", "d", "e"];
+        let new = ["a", "x", "c", "e", "f"];
+        assert_eq!(
+            diff_lines(&old, &new),
+            [
+                Hunk { old: 1..2, new: 1..2 },
+                Hunk { old: 3..4, new: 3..3 },
+                Hunk { old: 5..5, new: 4..5 },
+            ]
+        );
+        assert!(diff_lines(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn diff_formatted_text() {
+        let diff = |old: &str, new: &str| diff_lines(&lines(old), &lines(new));
+        // Adding the final line ending replaces the last line
+        assert_eq!(diff("let x = 1\nx", "let x = 1\nx\n"), [Hunk { old: 1..2, new: 1..2 }]);
+        // Line endings are part of the lines
+        assert_eq!(diff("a\r\nb\r\n", "a\r\nb\n"), [Hunk { old: 1..2, new: 1..2 }]);
+        assert_eq!(diff("a\n", "\na\n"), [Hunk { old: 0..0, new: 0..1 }]);
+        assert_eq!(diff("a\n\n\n", "a\n"), [Hunk { old: 1..3, new: 1..1 }]);
+        assert_eq!(diff("", "a\n"), [Hunk { old: 0..0, new: 0..1 }]);
+    }
+}
@@ -56,11 +56,13 @@ export function activate(context: ExtensionContext) {
 	let clientOptions: LanguageClientOptions = {
 		// Register the server for plain text documents
 		documentSelector: ['gluon'],
+		// Pass the settings to the server so they are used from the start
+		initializationOptions: workspace.getConfiguration('gluon'),
 		synchronize: {
 			// Synchronize the setting section 'languageServerExample' to the server
 			configurationSection: 'gluon',
-			// Notify the server about file changes to '.clientrc files contain in the workspace
-			fileEvents: workspace.createFileSystemWatcher('**/.clientrc')
+			// Notify the server about changes to gluon files in the workspace
+			fileEvents: workspace.createFileSystemWatcher('**/*.glu')
 		}
 	}
 	
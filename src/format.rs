@@ -0,0 +1,61 @@
+//! Formatting of gluon files outside of an editor, using the same formatter as
+//! `textDocument/formatting`
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+use gluon::parser::format_expr;
+
+use diff::{self, diff_lines};
+
+use super::glu_files_in;
+
+/// Formats the files in `paths`, searching directories for gluon files. With `check` the files
+/// are left unchanged and a diff is written to `output` for each file which is not formatted.
+/// Returns `true` if a file could not be formatted or, with `check`, is not formatted.
+pub fn format<W>(mut output: W, paths: &[PathBuf], check: bool) -> io::Result<bool>
+where
+    W: Write,
+{
+    let mut failed = false;
+    for path in glu_files_in(paths) {
+        let mut source = String::new();
+        try!(File::open(&path).and_then(|mut file| file.read_to_string(&mut source)));
+
+        let formatted = match format_expr(&source) {
+            Ok(formatted) => formatted,
+            Err(err) => {
+                try!(writeln!(output, "error: Unable to format {}\n{}", path.display(), err));
+                failed = true;
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+
+        if check {
+            failed = true;
+            let old = diff::lines(&source);
+            let new = diff::lines(&formatted);
+            for hunk in diff_lines(&old, &new) {
+                try!(writeln!(
+                    output,
+                    "Diff in {} at line {}:",
+                    path.display(),
+                    hunk.old.start + 1
+                ));
+                for line in &old[hunk.old] {
+                    try!(writeln!(output, "-{}", line.trim_right_matches(&['\r', '\n'][..])));
+                }
+                for line in &new[hunk.new] {
+                    try!(writeln!(output, "+{}", line.trim_right_matches(&['\r', '\n'][..])));
+                }
+            }
+        } else {
+            try!(File::create(&path).and_then(|mut file| file.write_all(formatted.as_bytes())));
+        }
+    }
+    Ok(failed)
+}
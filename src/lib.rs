@@ -2,6 +2,7 @@
 #![cfg_attr(feature = "serde_macros", plugin(serde_macros))]
 
 extern crate serde;
+#[macro_use]
 extern crate serde_json;
 #[macro_use]
 extern crate serde_derive;
@@ -19,38 +20,50 @@ extern crate url_serde;
 extern crate languageserver_types;
 
 pub mod rpc;
+pub mod check;
+pub mod format;
+mod diff;
+mod query;
 
-use jsonrpc_core::{IoHandler, RpcNotificationSimple, Params, Value};
+use jsonrpc_core::{ErrorCode, IoHandler, RpcNotificationSimple, Params, Value};
 
 use url::Url;
 
-use gluon::base::ast::{SpannedExpr, Typed};
+use gluon::base::ast::{self, Expr, Literal, MutVisitor, Pattern, SpannedExpr, SpannedPattern,
+                       Typed, TypedIdent};
 use gluon::base::error::Errors;
-use gluon::base::fnv::FnvMap;
+use gluon::base::fnv::{FnvMap, FnvSet};
 use gluon::base::metadata::Metadata;
 use gluon::base::pos::{self, BytePos, Line, Span};
 use gluon::base::source;
-use gluon::base::symbol::Symbol;
+use gluon::base::symbol::{Symbol, Symbols};
+use gluon::base::resolve;
+use gluon::base::types::{arg_iter, Alias, ArcType, BuiltinType, Type, TypeEnv};
 use gluon::check::completion;
 use gluon::import::{Import, Importer};
 use gluon::vm::internal::Value as GluonValue;
 use gluon::vm::thread::{Thread, ThreadInternal};
-use gluon::vm::macros::Error as MacroError;
+use gluon::vm::macros::{Error as MacroError, Macro, MacroExpander};
 use gluon::{Compiler, Error as GluonError, Result as GluonResult, RootedThread, new_vm,
             filename_to_module};
 
-use std::collections::{BTreeMap, VecDeque};
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::env;
 use std::error::Error as StdError;
 use std::fmt;
 use std::fs;
-use std::io::{self, BufReader, Write};
-use std::path::PathBuf;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::ops::Deref;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
 use std::str;
-use std::sync::{Arc, Condvar, Mutex};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
 use std::sync::atomic;
 use std::sync::atomic::AtomicBool;
 use std::thread;
+use std::time::Duration;
 
 use languageserver_types::*;
 
@@ -58,10 +71,12 @@ use futures::{BoxFuture, Future, IntoFuture};
 
 use rpc::*;
 
+use diff::{diff_lines, Hunk};
+
 macro_rules! log_message {
-    ($($ts: tt)+) => {
+    ($client: expr, $($ts: tt)+) => {
         if log_enabled!(log::LogLevel::Debug) {
-            log_message(format!( $($ts)+ ))
+            log_message(&$client, format!( $($ts)+ ))
         }
     }
 }
@@ -78,30 +93,73 @@ where
                     Ok(value) => {
                         self.0.execute(value);
                     }
-                    Err(err) => log_message!("Invalid parameters. Reason: {}", err),
+                    Err(err) => debug!("Invalid parameters. Reason: {}", err),
                 }
             }
-            _ => log_message!("Invalid parameters: {:?}", param),
+            _ => debug!("Invalid parameters: {:?}", param),
         }
     }
 }
 
-fn log_message(message: String) {
+fn log_message(client: &ClientSender, message: String) {
     debug!("{}", message);
-    let r = format!(
-        r#"{{"jsonrpc": "2.0", "method": "window/logMessage", "params": {} }}"#,
-        serde_json::to_value(&LogMessageParams {
+    client.send_notification(
+        "window/logMessage",
+        &LogMessageParams {
             typ: MessageType::Log,
             message: message,
-        }).unwrap()
+        },
     );
-    print!("Content-Length: {}\r\n\r\n{}", r.len(), r);
 }
 
 struct Module {
     lines: source::Lines,
     expr: SpannedExpr<Symbol>,
     source_string: String,
+    /// The uri which the client opened the module with, `None` for modules loaded by `import!`
+    uri: Option<Url>,
+    /// The modules which this module imports
+    imports: Vec<String>,
+}
+
+impl Module {
+    fn new(
+        modules: &FnvMap<String, Module>,
+        name: &str,
+        expr: SpannedExpr<Symbol>,
+        source: &str,
+        uri: Option<Url>,
+    ) -> Module {
+        let imports = {
+            let occurrences = query::occurrences(&expr, source);
+            query::imported_modules(&occurrences, |module| {
+                module != name && modules.contains_key(module)
+            })
+        };
+        Module {
+            lines: source::Lines::new(source),
+            expr: expr,
+            source_string: source.into(),
+            uri: uri,
+            imports: imports,
+        }
+    }
+
+    fn position_to_byte_pos(
+        &self,
+        encoding: PositionEncoding,
+        position: &Position,
+    ) -> Result<BytePos, ServerError<()>> {
+        position_to_byte_pos(encoding, &self.lines, &self.source_string, position)
+    }
+
+    fn byte_span_to_range(
+        &self,
+        encoding: PositionEncoding,
+        span: Span<BytePos>,
+    ) -> Result<Range, ServerError<()>> {
+        byte_span_to_range(encoding, &self.lines, &self.source_string, span)
+    }
 }
 
 #[derive(Clone)]
@@ -126,16 +184,12 @@ impl Importer for CheckImporter {
         let TypecheckValue { expr, typ } =
             try!(macro_value.typecheck(compiler, vm, module_name, input));
 
-        let lines = source::Lines::new(input);
         let (metadata, _) = gluon::check::metadata::metadata(&*vm.global_env().get_env(), &expr);
-        self.0.lock().unwrap().insert(
-            module_name.into(),
-            Module {
-                lines: lines,
-                expr: expr,
-                source_string: input.into(),
-            },
-        );
+        {
+            let mut modules = self.0.lock().unwrap();
+            let module = Module::new(&modules, module_name, expr, input, None);
+            modules.insert(module_name.into(), module);
+        }
         // Insert a global to ensure the globals type can be looked up
         try!(
             vm.global_env()
@@ -145,34 +199,408 @@ impl Importer for CheckImporter {
     }
 }
 
-struct Initialize(RootedThread);
-impl LanguageServerCommand<InitializeParams> for Initialize {
-    type Output = InitializeResult;
+/// `import!` which loads the modules in `stale` from disk again even though the vm still has their
+/// globals, as the vm has no way to remove the global of a module which was deleted
+struct CheckImport {
+    import: Import<CheckImporter>,
+    /// Modules whose globals do not match their files, because the file was deleted or failed to
+    /// reload
+    stale: Mutex<FnvSet<String>>,
+    /// The file which each module was imported from, as module names can't be turned back into
+    /// file names which contain `.`
+    filenames: Mutex<FnvMap<String, String>>,
+    /// The modules found under the import paths, `None` until the paths are searched
+    disk_modules: Mutex<Option<Vec<(String, PathBuf)>>>,
+    /// The modules on disk which have been parsed to find their declarations
+    parsed_disk_modules: Mutex<FnvMap<PathBuf, Arc<DiskModule>>>,
+    /// The paths which the `importPaths` setting added, leaving out the paths which were searched
+    /// already so that they are kept when the setting changes
+    setting_paths: Mutex<Vec<PathBuf>>,
+}
+
+impl CheckImport {
+    fn new() -> CheckImport {
+        CheckImport {
+            import: Import::new(CheckImporter::new()),
+            stale: Mutex::new(FnvSet::default()),
+            filenames: Mutex::new(FnvMap::default()),
+            disk_modules: Mutex::new(None),
+            parsed_disk_modules: Mutex::new(FnvMap::default()),
+            setting_paths: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns the expression of the module `name` with its imports inlined in the same way
+    fn inline_import(
+        &self,
+        macros: &mut MacroExpander,
+        args: &[SpannedExpr<Symbol>],
+        filename: &str,
+        name: String,
+    ) -> Result<SpannedExpr<Symbol>, MacroError> {
+        use gluon::compiler_pipeline::*;
+
+        let cyclic = INLINED_IMPORTS.with(|inlined| {
+            let mut inlined = inlined.borrow_mut();
+            let inlined = inlined.as_mut().expect("Inlined imports");
+            if inlined.contains(&name) {
+                return true;
+            }
+            inlined.push(name.clone());
+            false
+        });
+        if cyclic {
+            return Err(format!("Module `{}` imports itself", name).into());
+        }
+        let span = args[0].span;
+        let result = self.import.read_file(filename).and_then(|source| {
+            let mut compiler = Compiler::new();
+            (&*source)
+                .expand_macro_with(&mut compiler, macros, &name)
+                .map(|value| {
+                    let mut expr = value.expr;
+                    // Errors in the module are reported at the import as they are not in the
+                    // importing file
+                    ReplaceSpans(span).visit_expr(&mut expr);
+                    expr
+                })
+                .map_err(|err| err.into())
+        });
+        INLINED_IMPORTS.with(|inlined| {
+            inlined.borrow_mut().as_mut().expect("Inlined imports").pop()
+        });
+        result
+    }
+
+    /// Forgets what is known about the file at `path`, as it was changed, created or deleted
+    fn invalidate_disk_module(&self, path: &Path) {
+        // The directory is resolved instead of the file as a deleted file has no canonical path
+        let path = match (path.parent().map(fs::canonicalize), path.file_name()) {
+            (Some(Ok(dir)), Some(file_name)) => dir.join(file_name),
+            _ => path.to_owned(),
+        };
+        self.parsed_disk_modules.lock().unwrap().remove(&path);
+
+        let mut disk_modules = self.disk_modules.lock().unwrap();
+        let listed = disk_modules.as_ref().map_or(false, |modules| {
+            modules.iter().any(|&(_, ref module_path)| *module_path == path)
+        });
+        if listed != path.exists() {
+            *disk_modules = None;
+        }
+    }
+}
+
+thread_local! {
+    /// The modules whose expressions are being inlined by `import!` instead of loading them, or
+    /// `None` if imports are loaded
+    static INLINED_IMPORTS: RefCell<Option<Vec<String>>> = RefCell::new(None);
+}
+
+/// Runs `f` with `import!` inlining the expressions of the modules which are not loaded already
+/// instead of loading them
+fn without_loading_imports<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    /// Restores the previous mode even if `f` panics, as request threads outlive panics
+    struct Restore(Option<Vec<String>>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let previous = self.0.take();
+            INLINED_IMPORTS.with(|inlined| *inlined.borrow_mut() = previous);
+        }
+    }
+
+    let _restore = Restore(INLINED_IMPORTS.with(|inlined| inlined.replace(Some(Vec::new()))));
+    f()
+}
+
+/// Replaces every span in an expression
+struct ReplaceSpans(Span<BytePos>);
+
+impl MutVisitor for ReplaceSpans {
+    type Ident = Symbol;
+
+    fn visit_expr(&mut self, expr: &mut SpannedExpr<Symbol>) {
+        let span = self.0;
+        expr.span = span;
+        match expr.value {
+            Expr::Infix(_, ref mut op, _) => op.span = span,
+            Expr::Record {
+                ref mut types,
+                ref mut exprs,
+                ..
+            } => {
+                for field in types {
+                    field.name.span = span;
+                }
+                for field in exprs {
+                    field.name.span = span;
+                }
+            }
+            Expr::TypeBindings(ref mut binds, _) => for bind in binds {
+                bind.name.span = span;
+                bind.alias.span = span;
+            },
+            _ => (),
+        }
+        ast::walk_mut_expr(self, expr);
+    }
+
+    fn visit_pattern(&mut self, pattern: &mut SpannedPattern<Symbol>) {
+        let span = self.0;
+        pattern.span = span;
+        if let Pattern::Record {
+            ref mut types,
+            ref mut fields,
+            ..
+        } = pattern.value
+        {
+            for field in types {
+                field.name.span = span;
+            }
+            for field in fields {
+                field.name.span = span;
+            }
+        }
+        ast::walk_mut_pattern(self, &mut pattern.value);
+    }
+}
+
+impl Deref for CheckImport {
+    type Target = Import<CheckImporter>;
+    fn deref(&self) -> &Import<CheckImporter> {
+        &self.import
+    }
+}
+
+impl Macro for CheckImport {
+    fn expand(
+        &self,
+        macros: &mut MacroExpander,
+        args: &mut [SpannedExpr<Symbol>],
+    ) -> Result<SpannedExpr<Symbol>, MacroError> {
+        use gluon::compiler_pipeline::*;
+
+        let filename = match args.first().map(|arg| &arg.value) {
+            Some(&Expr::Literal(Literal::String(ref filename))) => filename.clone(),
+            _ => return self.import.expand(macros, args),
+        };
+        let name = filename_to_module(&filename);
+        self.filenames
+            .lock()
+            .unwrap()
+            .entry(name.clone())
+            .or_insert_with(|| filename.clone());
+        let inlining = INLINED_IMPORTS.with(|inlined| inlined.borrow().is_some());
+        if inlining {
+            if macros.vm.global_env().global_exists(&name) {
+                return self.import.expand(macros, args);
+            }
+            return self.inline_import(macros, args, &filename, name);
+        }
+        // Removing the module before loading it also stops a cyclic import from loading it again
+        if !self.stale.lock().unwrap().remove(&name) {
+            return self.import.expand(macros, args);
+        }
+        let result = (|| -> Result<_, MacroError> {
+            let source = try!(self.import.read_file(&filename));
+            let mut compiler = Compiler::new();
+            let errors = macros.errors.len();
+            let value = try!((&*source).expand_macro_with(&mut compiler, macros, &name));
+            if errors != macros.errors.len() {
+                if let Some(err) = macros.errors.pop() {
+                    return Err(err);
+                }
+            }
+            self.import
+                .importer
+                .import(&mut compiler, macros.vm, &name, &source, value.expr)
+        })();
+        if let Err(err) = result {
+            self.stale.lock().unwrap().insert(name);
+            return Err(err);
+        }
+        Ok(pos::spanned(
+            args[0].span,
+            Expr::Ident(TypedIdent::new(Symbol::from(&name[..]))),
+        ))
+    }
+}
+
+/// Settings which the client can change through `initializationOptions` or the `gluon`
+/// configuration section
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct Settings {
+    /// The maximum number of diagnostics published for each document
+    max_number_of_problems: usize,
+    /// Paths searched by `import!` in addition to the workspace root
+    import_paths: Vec<PathBuf>,
+    /// Milliseconds to wait after a change before checking the changed documents
+    debounce: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            max_number_of_problems: 100,
+            import_paths: Vec::new(),
+            debounce: 0,
+        }
+    }
+}
+
+/// Replaces the current settings with the settings in `value`, returning `true` if they changed
+fn update_settings(
+    client: &ClientSender,
+    thread: &Thread,
+    settings: &Mutex<Settings>,
+    value: &Value,
+) -> bool {
+    // Configuration changes contain every synchronized section
+    let value = value.get("gluon").unwrap_or(value);
+    let new_settings: Settings = match serde_json::from_value(value.clone()) {
+        Ok(new_settings) => new_settings,
+        Err(err) => {
+            log_message!(client, "Invalid settings. Reason: {}", err);
+            return false;
+        }
+    };
+    let mut settings = settings.lock().unwrap();
+    if *settings == new_settings {
+        return false;
+    }
+
+    let import = thread.get_macros().get("import").expect("Import macro");
+    let import = import
+        .downcast_ref::<CheckImport>()
+        .expect("Check importer");
+    let mut paths = import.paths.write().unwrap();
+    let mut setting_paths = import.setting_paths.lock().unwrap();
+    paths.retain(|path| !setting_paths.contains(path));
+    setting_paths.clear();
+    for path in &new_settings.import_paths {
+        if !paths.contains(path) {
+            paths.push(path.clone());
+            setting_paths.push(path.clone());
+        }
+    }
+    *import.disk_modules.lock().unwrap() = None;
+
+    *settings = new_settings;
+    true
+}
+
+/// `InitializeParams` along with the client capabilities which `languageserver_types` does not
+/// know about yet
+struct InitializeRequest {
+    params: InitializeParams,
+    /// The encodings which the client can count the characters of a `Position` in
+    position_encodings: Vec<String>,
+}
+
+impl<'de> serde::Deserialize<'de> for InitializeRequest {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::de::Error;
+
+        let value = try!(Value::deserialize(deserializer));
+        let position_encodings = value
+            .pointer("/capabilities/general/positionEncodings")
+            .and_then(|encodings| serde_json::from_value(encodings.clone()).ok())
+            .unwrap_or_default();
+        let params = try!(serde_json::from_value(value).map_err(D::Error::custom));
+        Ok(InitializeRequest {
+            params: params,
+            position_encodings: position_encodings,
+        })
+    }
+}
+
+struct Initialize {
+    client: ClientSender,
+    thread: RootedThread,
+    settings: Arc<Mutex<Settings>>,
+    /// Set if the client can insert completions as snippets
+    snippet_support: Arc<AtomicBool>,
+    encoding: Encoding,
+}
+impl LanguageServerCommand<InitializeRequest> for Initialize {
+    type Output = Value;
     type Error = InitializeError;
     fn execute(
         &self,
-        change: InitializeParams,
-    ) -> BoxFuture<InitializeResult, ServerError<InitializeError>> {
-        let import = self.0.get_macros().get("import").expect("Import macro");
+        request: InitializeRequest,
+    ) -> BoxFuture<Value, ServerError<InitializeError>> {
+        let change = request.params;
+        let import = self.thread.get_macros().get("import").expect("Import macro");
         let import = import
-            .downcast_ref::<Import<CheckImporter>>()
+            .downcast_ref::<CheckImport>()
             .expect("Check importer");
         if let Some(ref path) = change.root_path {
             import.add_path(path);
+            *import.disk_modules.lock().unwrap() = None;
         }
-        Ok(InitializeResult {
+        if let Some(ref options) = change.initialization_options {
+            update_settings(&self.client, &self.thread, &self.settings, options);
+        }
+        let snippet_support = change
+            .capabilities
+            .text_document
+            .as_ref()
+            .and_then(|text_document| text_document.completion.as_ref())
+            .and_then(|completion| completion.completion_item.as_ref())
+            .and_then(|completion_item| completion_item.snippet_support)
+            .unwrap_or(false);
+        self.snippet_support.store(snippet_support, atomic::Ordering::SeqCst);
+        let mut result = serde_json::to_value(InitializeResult {
             capabilities: ServerCapabilities {
-                text_document_sync: Some(TextDocumentSyncKind::Full),
+                text_document_sync: Some(TextDocumentSyncKind::Incremental),
                 completion_provider: Some(CompletionOptions {
                     resolve_provider: Some(true),
                     trigger_characters: vec![".".into()],
                 }),
                 hover_provider: Some(true),
+                signature_help_provider: Some(SignatureHelpOptions {
+                    trigger_characters: Some(vec![" ".into(), "(".into()]),
+                }),
+                definition_provider: Some(true),
+                references_provider: Some(true),
+                document_highlight_provider: Some(true),
+                document_symbol_provider: Some(true),
+                workspace_symbol_provider: Some(true),
                 document_formatting_provider: Some(true),
+                document_range_formatting_provider: Some(true),
+                document_on_type_formatting_provider: Some(DocumentOnTypeFormattingOptions {
+                    first_trigger_character: "\n".into(),
+                    more_trigger_character: None,
+                }),
+                rename_provider: Some(true),
                 ..ServerCapabilities::default()
             },
-        }).into_future()
-            .boxed()
+        }).expect("InitializeResult");
+        // `languageserver_types` does not know about `prepareRename` or save notifications yet
+        result["capabilities"]["renameProvider"] = json!({ "prepareProvider": true });
+        result["capabilities"]["textDocumentSync"] = json!({
+            "openClose": true,
+            "change": TextDocumentSyncKind::Incremental,
+            "save": { "includeText": false },
+        });
+
+        // Positions are counted in UTF-16 code units unless the client agrees to use bytes, which
+        // is how the source is stored
+        let utf8 = request.position_encodings.iter().any(|encoding| encoding == "utf-8");
+        *self.encoding.lock().unwrap() = if utf8 {
+            PositionEncoding::Utf8
+        } else {
+            PositionEncoding::Utf16
+        };
+        result["capabilities"]["positionEncoding"] = json!(if utf8 { "utf-8" } else { "utf-16" });
+        Ok(result).into_future().boxed()
     }
 
     fn invalid_params(&self) -> Option<Self::Error> {
@@ -180,32 +608,59 @@ impl LanguageServerCommand<InitializeParams> for Initialize {
     }
 }
 
-fn retrieve_expr<F, R>(thread: &Thread, text_document_uri: &Url, f: F) -> Result<R, ServerError<()>>
+fn with_modules<F, R>(thread: &Thread, f: F) -> R
 where
-    F: FnOnce(&Module) -> Result<R, ServerError<()>>,
+    F: FnOnce(&FnvMap<String, Module>) -> R,
 {
-    let filename = strip_file_prefix_with_thread(thread, text_document_uri);
-    let module = filename_to_module(&filename);
     let import = thread.get_macros().get("import").expect("Import macro");
     let import = import
-        .downcast_ref::<Import<CheckImporter>>()
+        .downcast_ref::<CheckImport>()
         .expect("Check importer");
     let importer = import.importer.0.lock().unwrap();
-    let source_module = try!(importer.get(&module).ok_or_else(|| {
+    f(&importer)
+}
+
+fn with_modules_mut<F, R>(thread: &Thread, f: F) -> R
+where
+    F: FnOnce(&mut FnvMap<String, Module>) -> R,
+{
+    let import = thread.get_macros().get("import").expect("Import macro");
+    let import = import
+        .downcast_ref::<CheckImport>()
+        .expect("Check importer");
+    let mut importer = import.importer.0.lock().unwrap();
+    f(&mut importer)
+}
+
+fn get_module<'a>(
+    modules: &'a FnvMap<String, Module>,
+    module: &str,
+) -> Result<&'a Module, ServerError<()>> {
+    modules.get(module).ok_or_else(|| {
         ServerError {
+            code: ErrorCode::InternalError,
             message: format!(
                 "Module `{}` is not defined\n{:?}",
                 module,
-                importer.keys().collect::<Vec<_>>()
+                modules.keys().collect::<Vec<_>>()
             ),
             data: None,
         }
-    }));
-    f(source_module)
+    })
+}
+
+fn retrieve_expr<F, R>(thread: &Thread, text_document_uri: &Url, f: F) -> Result<R, ServerError<()>>
+where
+    F: FnOnce(&Module) -> Result<R, ServerError<()>>,
+{
+    let filename = strip_file_prefix_with_thread(thread, text_document_uri);
+    let module = filename_to_module(&filename);
+    with_modules(thread, |modules| f(try!(get_module(modules, &module))))
 }
 
 fn retrieve_expr_with_pos<F, R>(
     thread: &Thread,
+    encoding: PositionEncoding,
     text_document_uri: &Url,
     position: &Position,
     f: F,
@@ -214,29 +669,68 @@ where
     F: FnOnce(&SpannedExpr<Symbol>, BytePos) -> Result<R, ServerError<()>>,
 {
     retrieve_expr(thread, text_document_uri, |module| {
-        let Module {
-            ref expr,
-            ref lines,
-            ..
-        } = *module;
-        let line_pos = try!(lines.line(Line::from(position.line as usize)).ok_or_else(
-            || {
-                ServerError {
-                    message: format!(
-                        "Position ({}, {}) is out of range",
-                        position.line,
-                        position.character
-                    ),
-                    data: None,
-                }
-            },
-        ));
-        let byte_pos = line_pos + BytePos::from(position.character as usize);
+        let byte_pos = try!(module.position_to_byte_pos(encoding, position));
 
-        f(expr, byte_pos)
+        f(&module.expr, byte_pos)
     })
 }
 
+/// How the characters of a `Position` are counted
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PositionEncoding {
+    /// UTF-8 code units (bytes), which is how the source is stored
+    Utf8,
+    /// UTF-16 code units, which clients use unless they agree to something else
+    Utf16,
+}
+
+impl PositionEncoding {
+    /// Returns the number of characters in `text` as the client counts them in a `Position`
+    fn len(self, text: &str) -> usize {
+        match self {
+            PositionEncoding::Utf8 => text.len(),
+            PositionEncoding::Utf16 => text.encode_utf16().count(),
+        }
+    }
+}
+
+/// The `PositionEncoding` which the client agreed to during `initialize`
+type Encoding = Arc<Mutex<PositionEncoding>>;
+
+fn position_to_byte_pos(
+    encoding: PositionEncoding,
+    lines: &source::Lines,
+    source: &str,
+    position: &Position,
+) -> Result<BytePos, ServerError<()>> {
+    let line_pos = try!(lines.line(Line::from(position.line as usize)).ok_or_else(
+        || {
+            ServerError {
+                code: ErrorCode::InternalError,
+                message: format!(
+                    "Position ({}, {}) is out of range",
+                    position.line,
+                    position.character
+                ),
+                data: None,
+            }
+        },
+    ));
+    let line = &source[line_pos.to_usize()..];
+    let line = &line[..line.find('\n').unwrap_or(line.len())];
+    let line = line.trim_right_matches('\r');
+
+    let mut character = 0;
+    for (i, c) in line.char_indices() {
+        if character >= position.character as usize {
+            return Ok(line_pos + BytePos::from(i));
+        }
+        character += encoding.len(&line[i..i + c.len_utf8()]);
+    }
+    // Positions past the end of a line refer to the end of the line
+    Ok(line_pos + BytePos::from(line.len()))
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct CompletionData {
     #[serde(with = "url_serde")]
@@ -244,47 +738,359 @@ pub struct CompletionData {
     pub position: Position,
 }
 
-struct Completion(RootedThread);
+/// `CompletionItemKind::EnumMember`, which `languageserver_types` does not know about yet
+const ENUM_MEMBER_COMPLETION: u64 = 20;
+/// `CompletionItemKind::Struct`, which `languageserver_types` does not know about yet
+const STRUCT_COMPLETION: u64 = 22;
+
+/// Identifies completion items for modules in `import!` so that `completionItem/resolve` can
+/// find their types
+#[derive(Serialize, Deserialize)]
+struct ModuleCompletionData {
+    module: String,
+}
+
+/// The standard library modules which `Import` embeds in the binary
+const STD_MODULES: &[&str] = &[
+    "std.prelude",
+    "std.types",
+    "std.map",
+    "std.string",
+    "std.state",
+    "std.test",
+    "std.writer",
+];
+
+/// Returns the index in `line` where the path of an `import!` starts if `line` ends inside the
+/// path
+fn import_path_start(line: &str) -> Option<usize> {
+    let import = match line.rfind("import!") {
+        Some(import) => import + "import!".len(),
+        None => return None,
+    };
+    let rest = line[import..].trim_left();
+    // The argument may be in parentheses
+    let rest = if rest.starts_with('(') {
+        rest[1..].trim_left()
+    } else {
+        rest
+    };
+    let path = line.len() - rest.len();
+    if rest.starts_with('"') && !rest[1..].contains('"') {
+        Some(path + 1)
+    } else {
+        None
+    }
+}
+
+/// Completes the path of the module at `position` if it is inside an `import!`
+fn import_completion(
+    thread: &Thread,
+    encoding: PositionEncoding,
+    source: &str,
+    position: &Position,
+    current_module: &str,
+) -> Result<Option<Vec<CompletionItem>>, ServerError<()>> {
+    let lines = source::Lines::new(source);
+    let byte_pos = try!(position_to_byte_pos(encoding, &lines, source, position)).to_usize();
+    let line_start = byte_pos - lines
+        .location(BytePos::from(byte_pos))
+        .map_or(0, |location| location.column.to_usize());
+    let line = &source[line_start..byte_pos];
+    let path_start = match import_path_start(line) {
+        Some(path_start) => path_start,
+        None => return Ok(None),
+    };
+    let prefix = &line[path_start..];
+
+    let mut module_names: BTreeMap<String, Option<String>> = BTreeMap::new();
+    module_names.extend(STD_MODULES.iter().map(|name| (name.to_string(), None)));
+    module_names.extend(modules_on_disk(thread).into_iter().map(|(name, _)| (name, None)));
+    with_modules(thread, |modules| {
+        module_names.extend(modules.keys().map(|name| (name.clone(), None)))
+    });
+    module_names.remove(current_module);
+    {
+        let env = thread.get_env();
+        for (name, detail) in &mut module_names {
+            *detail = env.find_type(&Symbol::from(&name[..]))
+                .map(|typ| typ.to_string());
+        }
+    }
+
+    let range = Range {
+        start: try!(byte_pos_to_position(
+            encoding,
+            &lines,
+            source,
+            BytePos::from(line_start + path_start),
+        )),
+        end: *position,
+    };
+    Ok(Some(
+        module_names
+            .into_iter()
+            .filter_map(|(name, detail)| {
+                let path = format!("{}.glu", name.replace('.', "/"));
+                if !path.starts_with(prefix) {
+                    return None;
+                }
+                Some(CompletionItem {
+                    label: path.clone(),
+                    kind: Some(CompletionItemKind::Module),
+                    detail: detail,
+                    text_edit: Some(TextEdit {
+                        range: range,
+                        new_text: path,
+                    }),
+                    data: Some(
+                        serde_json::to_value(ModuleCompletionData { module: name })
+                            .expect("ModuleCompletionData"),
+                    ),
+                    ..CompletionItem::default()
+                })
+            })
+            .collect(),
+    ))
+}
+
+/// Returns the type of the record exported by the module `name`. Modules which are not loaded yet
+/// are typechecked without loading them or the modules they import, so the types which come from
+/// modules which are not loaded are unknown
+fn module_type(thread: &Thread, name: &str) -> Result<ArcType, ServerError<()>> {
+    if let Some(typ) = thread.get_env().find_type(&Symbol::from(name)) {
+        return Ok(typ.clone());
+    }
+    let source = {
+        let import = thread.get_macros().get("import").expect("Import macro");
+        let import = import
+            .downcast_ref::<CheckImport>()
+            .expect("Check importer");
+        let filename = import.filenames.lock().unwrap().get(name).cloned();
+        let filename = filename.unwrap_or_else(|| format!("{}.glu", name.replace('.', "/")));
+        try!(import.read_file(filename))
+    };
+    let (_, typ, _) = try!(without_loading_imports(|| check_expr(thread, name, &source)));
+    Ok(typ)
+}
+
+/// Returns the completion kind of the type `alias` which is a field of a record
+fn type_field_kind(alias: &Alias<Symbol, ArcType>) -> u64 {
+    match **alias.typ() {
+        Type::Record(_) => STRUCT_COMPLETION,
+        _ => CompletionItemKind::Class as u64,
+    }
+}
+
+/// `InsertTextFormat::Snippet`, which `languageserver_types` does not know about yet
+const SNIPPET_FORMAT: u64 = 2;
+
+/// The keywords which start an expression along with a description and the snippet inserted for
+/// them
+const KEYWORD_SNIPPETS: &[(&str, &str, &str)] = &[
+    ("if", "if ... then ... else", "if ${1:condition} then ${2:value} else ${3:value}"),
+    ("let", "let ... in", "let ${1:name} = ${2:value}\nin\n$0"),
+    ("match", "match ... with", "match ${1:value} with\n| ${2:pattern} -> $0"),
+    ("type", "type ... =", "type ${1:Name} = ${2:Type}\n$0"),
+];
+
+/// Returns the name of the placeholder for an argument of type `typ`, such as `int` for `Int` or
+/// `option` for `Option a`
+fn placeholder_name(typ: &ArcType) -> String {
+    let name = match **typ {
+        Type::App(ref head, _) => match **head {
+            Type::Builtin(BuiltinType::Function) => "f",
+            _ => return placeholder_name(head),
+        },
+        Type::Builtin(builtin) => builtin.to_str(),
+        Type::Ident(ref id) => id.declared_name(),
+        Type::Alias(ref alias) => alias.name.declared_name(),
+        Type::Generic(ref generic) => generic.id.declared_name(),
+        Type::Record(_) => "record",
+        _ => "arg",
+    };
+    // Leave out the module which the type was defined in
+    let mut chars = name.rsplit('.').next().unwrap_or(name).chars();
+    match chars.next() {
+        Some(first) => first.to_lowercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Returns a snippet which calls the function `name` with a placeholder for each argument
+fn function_snippet(name: &str, typ: &ArcType) -> String {
+    let mut snippet = name.to_string();
+    for (i, arg) in arg_iter(typ).enumerate() {
+        snippet.push_str(&format!(" ${{{}:{}}}", i + 1, placeholder_name(arg)));
+    }
+    snippet
+}
+
+struct Completion {
+    thread: RootedThread,
+    documents: Documents,
+    /// Set if the client can insert completions as snippets
+    snippet_support: Arc<AtomicBool>,
+    encoding: Encoding,
+}
 impl LanguageServerCommand<TextDocumentPositionParams> for Completion {
-    type Output = Vec<CompletionItem>;
+    type Output = Vec<Value>;
     type Error = ();
     fn execute(
         &self,
         change: TextDocumentPositionParams,
-    ) -> BoxFuture<Vec<CompletionItem>, ServerError<()>> {
+    ) -> BoxFuture<Vec<Value>, ServerError<()>> {
         (|| -> Result<_, _> {
-            let thread = &self.0;
-            let suggestions = retrieve_expr_with_pos(
-                thread,
-                &change.text_document.uri,
-                &change.position,
-                |expr, byte_pos| Ok(completion::suggest(&*thread.get_env(), expr, byte_pos)),
-            )?;
+            let thread = &self.thread;
+            let encoding = *self.encoding.lock().unwrap();
+            let filename = strip_file_prefix_with_thread(thread, &change.text_document.uri);
+            let name = filename_to_module(&filename);
+            let data = serde_json::to_value(CompletionData {
+                text_document_uri: change.text_document.uri.clone(),
+                position: change.position,
+            }).expect("CompletionData");
+            // The module may not have been checked since the document was last changed
+            let source = open_document(&self.documents, &change.text_document.uri);
+            let source = match source {
+                Some(source) => Some(source),
+                None => with_modules(thread, |modules| {
+                    modules.get(&name).map(|module| module.source_string.clone())
+                }),
+            };
+            if let Some(source) = source {
+                if let Some(items) =
+                    try!(import_completion(thread, encoding, &source, &change.position, &name))
+                {
+                    return Ok(
+                        items
+                            .into_iter()
+                            .map(|item| serde_json::to_value(item).expect("CompletionItem"))
+                            .collect(),
+                    );
+                }
+            }
 
-            let mut items: Vec<_> = suggestions
-                .into_iter()
-                .map(|ident| {
-                    // Remove the `:Line x, Row y suffix`
-                    let name: &str = ident.name.as_ref();
-                    let label = String::from(name.split(':').next().unwrap_or(ident.name.as_ref()));
-                    CompletionItem {
-                        label: label,
-                        detail: Some(format!("{}", ident.typ)),
-                        kind: Some(CompletionItemKind::Variable),
-                        data: Some(
-                            serde_json::to_value(CompletionData {
-                                text_document_uri: change.text_document.uri.clone(),
-                                position: change.position,
-                            }).expect("CompletionData"),
-                        ),
-                        ..CompletionItem::default()
-                    }
-                })
-                .collect();
+            let snippet_support = self.snippet_support.load(atomic::Ordering::SeqCst);
+            let item = |label: String, detail: Option<String>| {
+                CompletionItem {
+                    label: label,
+                    detail: detail,
+                    data: Some(data.clone()),
+                    ..CompletionItem::default()
+                }
+            };
 
-            items.sort_by(|l, r| l.label.cmp(&r.label));
+            let mut items = try!(with_modules(thread, |modules| -> Result<_, ServerError<()>> {
+                let module = try!(get_module(modules, &name));
+                let byte_pos = try!(module.position_to_byte_pos(encoding, &change.position));
+                let env = thread.get_env();
+
+                // Only the fields of the expected type can be written where a field name goes
+                if let Some(record) = query::record_at(&*env, &module.expr, byte_pos) {
+                    let typ = resolve::remove_aliases(&*env, record.typ.clone());
+                    let unused = |name: &Symbol| {
+                        let name = name.declared_name();
+                        name.starts_with(&record.prefix[..]) &&
+                            !record.used.iter().any(|used| used == name)
+                    };
+                    let types = typ.type_field_iter()
+                        .filter(|field| unused(&field.name))
+                        .map(|field| {
+                            let label = field.name.declared_name().to_string();
+                            (item(label, None), type_field_kind(&field.typ))
+                        });
+                    let fields = typ.row_iter().filter(|field| unused(&field.name)).map(|field| {
+                        let label = field.name.declared_name().to_string();
+                        let detail = Some(field.typ.to_string());
+                        (item(label, detail), CompletionItemKind::Field as u64)
+                    });
+                    return Ok(types.chain(fields).collect());
+                }
 
-            Ok(items)
+                let suggestions = completion::suggest(&*env, &module.expr, byte_pos);
+                let projection = query::projection_at(&*env, &module.expr, byte_pos);
+                let module_bindings =
+                    query::module_bindings(&module.expr, |name| modules.contains_key(name));
+
+                let mut items: Vec<_> = suggestions
+                    .into_iter()
+                    .map(|ident| {
+                        // Remove the `:Line x, Row y suffix`
+                        let name: &str = ident.name.as_ref();
+                        let label = String::from(name.split(':').next().unwrap_or(name));
+                        let typ = resolve::remove_aliases(&*env, ident.typ.clone());
+                        let kind = if is_uppercase_identifier(&label) {
+                            ENUM_MEMBER_COMPLETION
+                        } else if arg_iter(&typ).next().is_some() {
+                            CompletionItemKind::Function as u64
+                        } else if projection.is_some() {
+                            CompletionItemKind::Field as u64
+                        } else if module_bindings.contains(&label) {
+                            CompletionItemKind::Module as u64
+                        } else {
+                            CompletionItemKind::Variable as u64
+                        };
+                        let mut item = item(label, Some(format!("{}", ident.typ)));
+                        // Operators are applied infix so they do not get a snippet
+                        if snippet_support && kind == CompletionItemKind::Function as u64 &&
+                            is_identifier(&item.label)
+                        {
+                            item.insert_text = Some(function_snippet(&item.label, &typ));
+                        }
+                        (item, kind)
+                    })
+                    .collect();
+
+                // Types can only be reached by projecting them out of a record
+                if let Some((typ, field)) = projection {
+                    let record = resolve::remove_aliases(&*env, typ);
+                    let types = record
+                        .type_field_iter()
+                        .filter(|field_type| {
+                            field_type.name.declared_name().starts_with(field.declared_name())
+                        })
+                        .map(|field_type| {
+                            let label = field_type.name.declared_name().to_string();
+                            (item(label, None), type_field_kind(&field_type.typ))
+                        });
+                    items.extend(types);
+                } else if snippet_support {
+                    let source = &module.source_string[..];
+                    let before = source.get(..byte_pos.to_usize()).unwrap_or(source);
+                    let word_start = before
+                        .trim_right_matches(|c: char| c.is_alphanumeric() || c == '_')
+                        .len();
+                    let word = &before[word_start..];
+                    let keywords = KEYWORD_SNIPPETS
+                        .iter()
+                        .filter(|&&(keyword, _, _)| keyword.starts_with(word))
+                        .map(|&(keyword, detail, snippet)| {
+                            let mut item = item(keyword.to_string(), Some(detail.to_string()));
+                            item.insert_text = Some(snippet.to_string());
+                            (item, CompletionItemKind::Keyword as u64)
+                        });
+                    items.extend(keywords);
+                }
+                Ok(items)
+            }));
+
+            items.sort_by(|l, r| l.0.label.cmp(&r.0.label));
+
+            Ok(
+                items
+                    .into_iter()
+                    .map(|(item, kind)| {
+                        // Only snippets are given an `insertText`
+                        let snippet = item.insert_text.is_some();
+                        let mut item = serde_json::to_value(item).expect("CompletionItem");
+                        item["kind"] = Value::from(kind);
+                        if snippet {
+                            item["insertTextFormat"] = Value::from(SNIPPET_FORMAT);
+                        }
+                        item
+                    })
+                    .collect(),
+            )
         })().into_future()
             .boxed()
     }
@@ -294,15 +1100,20 @@ impl LanguageServerCommand<TextDocumentPositionParams> for Completion {
     }
 }
 
-struct HoverCommand(RootedThread);
+struct HoverCommand {
+    thread: RootedThread,
+    encoding: Encoding,
+}
 impl LanguageServerCommand<TextDocumentPositionParams> for HoverCommand {
     type Output = Hover;
     type Error = ();
     fn execute(&self, change: TextDocumentPositionParams) -> BoxFuture<Hover, ServerError<()>> {
         (|| -> Result<_, _> {
-            let thread = &self.0;
+            let thread = &self.thread;
+            let encoding = *self.encoding.lock().unwrap();
             retrieve_expr_with_pos(
                 thread,
+                encoding,
                 &change.text_document.uri,
                 &change.position,
                 |expr, byte_pos| {
@@ -332,50 +1143,1630 @@ impl LanguageServerCommand<TextDocumentPositionParams> for HoverCommand {
             .boxed()
     }
 
-    fn invalid_params(&self) -> Option<Self::Error> {
-        None
+    fn invalid_params(&self) -> Option<Self::Error> {
+        None
+    }
+}
+
+/// Returns the signature of the function which is applied at `byte_pos`
+fn signature_help(
+    thread: &Thread,
+    expr: &SpannedExpr<Symbol>,
+    source: &str,
+    byte_pos: BytePos,
+) -> SignatureHelp {
+    let no_signature = SignatureHelp {
+        signatures: vec![],
+        active_signature: None,
+        active_parameter: None,
+    };
+    let (func_span, active_argument) = match query::application_at(expr, source, byte_pos) {
+        Some(application) => application,
+        None => return no_signature,
+    };
+    // Look at the last character of the function so that projections such as
+    // `string.len` find the field and not the record
+    let func_pos = func_span.end - BytePos::from(1);
+    let env = thread.get_env();
+    let typ = match completion::find(&*env, expr, func_pos) {
+        Ok(typ) => typ,
+        Err(()) => return no_signature,
+    };
+    let (_, metadata_map) = gluon::check::metadata::metadata(&*env, expr);
+    let comment = completion::get_metadata(&metadata_map, expr, func_pos)
+        .and_then(|metadata| metadata.comment.clone());
+
+    let name = &source[func_span.start.to_usize()..func_span.end.to_usize()];
+    let parameters: Vec<_> = arg_iter(&typ)
+        .map(|arg| {
+            ParameterInformation {
+                label: arg.to_string(),
+                documentation: None,
+            }
+        })
+        .collect();
+    // An identifier which is followed by a space need not be a function
+    if parameters.is_empty() {
+        return no_signature;
+    }
+    SignatureHelp {
+        active_parameter: if active_argument < parameters.len() {
+            Some(active_argument as u64)
+        } else {
+            None
+        },
+        signatures: vec![
+            SignatureInformation {
+                label: format!("{} : {}", name, typ),
+                documentation: comment,
+                parameters: Some(parameters),
+            },
+        ],
+        active_signature: Some(0),
+    }
+}
+
+/// Returns `source` with the opening parentheses between an identifier and `pos` replaced by
+/// spaces, since `f (` does not parse until the parenthesis is closed
+fn without_open_parens(source: &str, pos: usize) -> Option<String> {
+    let before = match source.get(..pos) {
+        Some(before) => before,
+        None => return None,
+    };
+    let trailing_start = before
+        .trim_right_matches(|c| c == ' ' || c == '\t' || c == '(')
+        .len();
+    let (applied, trailing) = before.split_at(trailing_start);
+    let ends_with_identifier = applied
+        .chars()
+        .next_back()
+        .map_or(false, |c| c.is_alphanumeric() || c == '_');
+    if !ends_with_identifier || !trailing.contains('(') {
+        return None;
+    }
+    let mut patched = String::with_capacity(source.len());
+    patched.push_str(applied);
+    patched.extend(trailing.chars().map(|c| if c == '(' { ' ' } else { c }));
+    patched.push_str(&source[pos..]);
+    Some(patched)
+}
+
+struct SignatureHelpCommand {
+    thread: RootedThread,
+    encoding: Encoding,
+}
+impl LanguageServerCommand<TextDocumentPositionParams> for SignatureHelpCommand {
+    type Output = SignatureHelp;
+    type Error = ();
+    fn execute(
+        &self,
+        change: TextDocumentPositionParams,
+    ) -> BoxFuture<SignatureHelp, ServerError<()>> {
+        (|| -> Result<_, _> {
+            let thread = &self.thread;
+            let encoding = *self.encoding.lock().unwrap();
+            let (help, byte_pos, source) =
+                try!(retrieve_expr(thread, &change.text_document.uri, |module| {
+                    let byte_pos = try!(module.position_to_byte_pos(encoding, &change.position));
+                    let help =
+                        signature_help(thread, &module.expr, &module.source_string, byte_pos);
+                    Ok((help, byte_pos, module.source_string.clone()))
+                }));
+            if !help.signatures.is_empty() {
+                return Ok(help);
+            }
+
+            // The module does not parse while the argument list is opened (`f (`) so check it
+            // again without the parentheses. Done outside of `retrieve_expr` as checking may
+            // import modules.
+            let patched = match without_open_parens(&source, byte_pos.to_usize()) {
+                Some(patched) => patched,
+                None => return Ok(help),
+            };
+            let filename = strip_file_prefix_with_thread(thread, &change.text_document.uri);
+            let name = filename_to_module(&filename);
+            let (expr, _, _) = try!(check_expr(thread, &name, &patched));
+            Ok(signature_help(thread, &expr, &patched, byte_pos))
+        })().into_future()
+            .boxed()
+    }
+
+    fn invalid_params(&self) -> Option<Self::Error> {
+        None
+    }
+}
+
+struct DefinitionCommand {
+    thread: RootedThread,
+    encoding: Encoding,
+}
+impl LanguageServerCommand<TextDocumentPositionParams> for DefinitionCommand {
+    type Output = Option<Location>;
+    type Error = ();
+    fn execute(
+        &self,
+        change: TextDocumentPositionParams,
+    ) -> BoxFuture<Option<Location>, ServerError<()>> {
+        (|| -> Result<_, _> {
+            let thread = &self.thread;
+            let encoding = *self.encoding.lock().unwrap();
+            let filename = strip_file_prefix_with_thread(thread, &change.text_document.uri);
+            let name = filename_to_module(&filename);
+            with_modules(thread, |modules| {
+                let module = try!(get_module(modules, &name));
+                let byte_pos = try!(module.position_to_byte_pos(encoding, &change.position));
+                let definition = query::definition(
+                    &module.expr,
+                    &module.source_string,
+                    byte_pos,
+                    |name| modules.contains_key(name),
+                );
+                Ok(match definition {
+                    Some(query::Definition::Local(span)) => Some(Location {
+                        uri: change.text_document.uri.clone(),
+                        range: try!(module.byte_span_to_range(encoding, span)),
+                    }),
+                    Some(query::Definition::Module(name)) => Some(Location {
+                        uri: try!(module_uri(thread, modules, &name)),
+                        range: Range::default(),
+                    }),
+                    Some(query::Definition::ModuleField { module, field }) => {
+                        let imported = try!(get_module(modules, &module));
+                        let range = match query::exported_field(&imported.expr, &field) {
+                            Some(span) => try!(imported.byte_span_to_range(encoding, span)),
+                            None => Range::default(),
+                        };
+                        Some(Location {
+                            uri: try!(module_uri(thread, modules, &module)),
+                            range: range,
+                        })
+                    }
+                    None => None,
+                })
+            })
+        })().into_future()
+            .boxed()
+    }
+
+    fn invalid_params(&self) -> Option<Self::Error> {
+        None
+    }
+}
+
+/// Returns the identifier at `position` along with what it refers to
+fn target_at(
+    encoding: PositionEncoding,
+    modules: &FnvMap<String, Module>,
+    module_name: &str,
+    position: &Position,
+) -> Result<Option<(query::Occurrence, query::Target)>, ServerError<()>> {
+    let module = try!(get_module(modules, module_name));
+    let byte_pos = try!(module.position_to_byte_pos(encoding, position));
+    let occurrences = query::occurrences(&module.expr, &module.source_string);
+    Ok(query::occurrence_at(&occurrences, byte_pos).and_then(|occurrence| {
+        query::target(
+            module_name,
+            &module.expr,
+            &occurrences,
+            occurrence,
+            &|name| modules.contains_key(name),
+        ).map(|target| (occurrence.clone(), target))
+    }))
+}
+
+/// Calls `f` with each module which may refer to `target` along with the module's occurrences and
+/// whether each of them refers to `target`
+fn for_each_reference<F>(
+    thread: &Thread,
+    modules: &FnvMap<String, Module>,
+    target: &query::Target,
+    mut f: F,
+) -> Result<(), ServerError<()>>
+where
+    F: FnMut(Url, &Module, &[query::Occurrence], &[bool]) -> Result<(), ServerError<()>>,
+{
+    let is_module = |name: &str| modules.contains_key(name);
+
+    // Only the defining module and the modules which import it can refer to `target`
+    let (defining_module, is_local) = match *target {
+        query::Target::Local { ref module, .. } => (module, true),
+        query::Target::ModuleField { ref module, .. } |
+        query::Target::Module(ref module) => (module, false),
+    };
+    let mut module_names: Vec<_> = modules.keys().collect();
+    module_names.sort();
+
+    let token = cancellation_token();
+    for module_name in module_names {
+        if token.is_cancelled() {
+            break;
+        }
+        let module = &modules[module_name];
+        let occurrences = query::occurrences(&module.expr, &module.source_string);
+        if module_name != defining_module &&
+            (is_local || !query::imports(&occurrences, defining_module))
+        {
+            continue;
+        }
+        let is_reference: Vec<_> = occurrences
+            .iter()
+            .map(|occurrence| {
+                query::target(
+                    module_name,
+                    &module.expr,
+                    &occurrences,
+                    occurrence,
+                    &is_module,
+                ).as_ref() == Some(target)
+            })
+            .collect();
+        try!(f(
+            try!(module_uri(thread, modules, module_name)),
+            module,
+            &occurrences,
+            &is_reference,
+        ));
+    }
+    Ok(())
+}
+
+struct ReferencesCommand {
+    thread: RootedThread,
+    encoding: Encoding,
+}
+impl LanguageServerCommand<ReferenceParams> for ReferencesCommand {
+    type Output = Vec<Location>;
+    type Error = ();
+    fn execute(&self, change: ReferenceParams) -> BoxFuture<Vec<Location>, ServerError<()>> {
+        (|| -> Result<_, _> {
+            let thread = &self.thread;
+            let encoding = *self.encoding.lock().unwrap();
+            let filename = strip_file_prefix_with_thread(thread, &change.text_document.uri);
+            let name = filename_to_module(&filename);
+            with_modules(thread, |modules| {
+                let target = match try!(target_at(encoding, modules, &name, &change.position)) {
+                    Some((_, target)) => target,
+                    None => return Ok(vec![]),
+                };
+
+                let mut locations = Vec::new();
+                try!(for_each_reference(
+                    thread,
+                    modules,
+                    &target,
+                    |uri, module, occurrences, is_reference| {
+                        let mut spans: Vec<_> = occurrences
+                            .iter()
+                            .zip(is_reference)
+                            .filter(|&(occurrence, &is_reference)| {
+                                is_reference &&
+                                    (change.context.include_declaration ||
+                                         !occurrence.definition)
+                            })
+                            .map(|(occurrence, _)| occurrence.span)
+                            .collect();
+                        spans.sort_by_key(|span| span.start);
+                        spans.dedup();
+
+                        for span in spans {
+                            locations.push(Location {
+                                uri: uri.clone(),
+                                range: try!(module.byte_span_to_range(encoding, span)),
+                            });
+                        }
+                        Ok(())
+                    },
+                ));
+                Ok(locations)
+            })
+        })().into_future()
+            .boxed()
+    }
+
+    fn invalid_params(&self) -> Option<Self::Error> {
+        None
+    }
+}
+
+struct DocumentHighlightCommand {
+    thread: RootedThread,
+    encoding: Encoding,
+}
+impl LanguageServerCommand<TextDocumentPositionParams> for DocumentHighlightCommand {
+    type Output = Vec<DocumentHighlight>;
+    type Error = ();
+    fn execute(
+        &self,
+        change: TextDocumentPositionParams,
+    ) -> BoxFuture<Vec<DocumentHighlight>, ServerError<()>> {
+        (|| -> Result<_, _> {
+            let thread = &self.thread;
+            let encoding = *self.encoding.lock().unwrap();
+            let filename = strip_file_prefix_with_thread(thread, &change.text_document.uri);
+            let name = filename_to_module(&filename);
+            with_modules(thread, |modules| {
+                let target = match try!(target_at(encoding, modules, &name, &change.position)) {
+                    Some((_, target)) => target,
+                    None => return Ok(vec![]),
+                };
+                let module = try!(get_module(modules, &name));
+                let occurrences = query::occurrences(&module.expr, &module.source_string);
+
+                let mut highlights: Vec<(Span<BytePos>, bool)> = Vec::new();
+                for occurrence in &occurrences {
+                    let target_of_occurrence = query::target(
+                        &name,
+                        &module.expr,
+                        &occurrences,
+                        occurrence,
+                        &|name| modules.contains_key(name),
+                    );
+                    if target_of_occurrence.as_ref() != Some(&target) {
+                        continue;
+                    }
+                    // `{ x }` both uses a field and defines `x`, prefer to show the definition
+                    match highlights.iter_mut().find(|&&mut (span, _)| span == occurrence.span) {
+                        Some(&mut (_, ref mut definition)) => *definition |= occurrence.definition,
+                        None => highlights.push((occurrence.span, occurrence.definition)),
+                    }
+                }
+                highlights.sort_by_key(|&(span, _)| span.start);
+
+                highlights
+                    .into_iter()
+                    .map(|(span, definition)| {
+                        Ok(DocumentHighlight {
+                            range: try!(module.byte_span_to_range(encoding, span)),
+                            kind: Some(if definition {
+                                DocumentHighlightKind::Write
+                            } else {
+                                DocumentHighlightKind::Read
+                            }),
+                        })
+                    })
+                    .collect()
+            })
+        })().into_future()
+            .boxed()
+    }
+
+    fn invalid_params(&self) -> Option<Self::Error> {
+        None
+    }
+}
+
+/// Returns the identifier at `position` if it can be renamed along with what it refers to
+fn rename_target_at(
+    thread: &Thread,
+    encoding: PositionEncoding,
+    modules: &FnvMap<String, Module>,
+    module_name: &str,
+    position: &Position,
+) -> Result<Option<(query::Occurrence, query::Target)>, ServerError<()>> {
+    let (occurrence, target) = match try!(target_at(encoding, modules, module_name, position)) {
+        Some(found) => found,
+        None => return Ok(None),
+    };
+    let defining_module = match target {
+        // Modules are named after their path so they can't be renamed from the source
+        query::Target::Module(_) => return Ok(None),
+        query::Target::Local { ref module, .. } |
+        query::Target::ModuleField { ref module, .. } => module,
+    };
+    // Modules which the client has not opened and which are not on disk (such as the standard
+    // library) can't be edited
+    let editable = modules
+        .get(defining_module)
+        .map_or(false, |module| module.uri.is_some()) ||
+        try!(module_uri(thread, modules, defining_module))
+            .to_file_path()
+            .map(|path| path.exists())
+            .unwrap_or(false);
+    let module = try!(get_module(modules, module_name));
+    let source = &module.source_string;
+    let is_identifier = source
+        .get(occurrence.span.start.to_usize()..occurrence.span.end.to_usize())
+        .map_or(false, is_identifier);
+    Ok(if editable && is_identifier {
+        Some((occurrence, target))
+    } else {
+        None
+    })
+}
+
+/// Returns `true` if `name` is a valid gluon identifier
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .map_or(false, |c| c.is_alphabetic() || c == '_') &&
+        chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// The words which can't be used as names
+const KEYWORDS: &[&str] = &[
+    "and", "else", "forall", "if", "in", "let", "match", "rec", "then", "type", "with",
+];
+
+fn is_uppercase_identifier(name: &str) -> bool {
+    name.chars().next().map_or(false, char::is_uppercase)
+}
+
+struct PrepareRenameCommand {
+    thread: RootedThread,
+    encoding: Encoding,
+}
+impl LanguageServerCommand<TextDocumentPositionParams> for PrepareRenameCommand {
+    type Output = Option<Range>;
+    type Error = ();
+    fn execute(
+        &self,
+        change: TextDocumentPositionParams,
+    ) -> BoxFuture<Option<Range>, ServerError<()>> {
+        (|| -> Result<_, _> {
+            let thread = &self.thread;
+            let encoding = *self.encoding.lock().unwrap();
+            let filename = strip_file_prefix_with_thread(thread, &change.text_document.uri);
+            let name = filename_to_module(&filename);
+            with_modules(thread, |modules| {
+                match try!(rename_target_at(thread, encoding, modules, &name, &change.position)) {
+                    Some((occurrence, _)) => {
+                        let module = try!(get_module(modules, &name));
+                        Ok(Some(try!(module.byte_span_to_range(encoding, occurrence.span))))
+                    }
+                    None => Ok(None),
+                }
+            })
+        })().into_future()
+            .boxed()
+    }
+
+    fn invalid_params(&self) -> Option<Self::Error> {
+        None
+    }
+}
+
+struct RenameCommand {
+    thread: RootedThread,
+    encoding: Encoding,
+}
+impl LanguageServerCommand<RenameParams> for RenameCommand {
+    type Output = WorkspaceEdit;
+    type Error = ();
+    fn execute(&self, change: RenameParams) -> BoxFuture<WorkspaceEdit, ServerError<()>> {
+        (|| -> Result<_, _> {
+            let thread = &self.thread;
+            let encoding = *self.encoding.lock().unwrap();
+            let filename = strip_file_prefix_with_thread(thread, &change.text_document.uri);
+            let name = filename_to_module(&filename);
+            with_modules(thread, |modules| {
+                let target =
+                    try!(rename_target_at(thread, encoding, modules, &name, &change.position));
+                let (occurrence, target) = match target {
+                    Some(found) => found,
+                    None => return Err(ServerError::from(&"The element can't be renamed")),
+                };
+                let module = try!(get_module(modules, &name));
+                let old_name = &module.source_string
+                    [occurrence.span.start.to_usize()..occurrence.span.end.to_usize()];
+                // Variables must stay lowercase and types and constructors uppercase
+                if !is_identifier(&change.new_name) ||
+                    KEYWORDS.contains(&&change.new_name[..]) ||
+                    is_uppercase_identifier(&change.new_name) != is_uppercase_identifier(old_name)
+                {
+                    return Err(ServerError::invalid_params(
+                        format!("`{}` is not a valid name for `{}`", change.new_name, old_name),
+                    ));
+                }
+
+                let mut changes = HashMap::new();
+                try!(for_each_reference(
+                    thread,
+                    modules,
+                    &target,
+                    |uri, module, occurrences, is_reference| {
+                        let renamed: Vec<_> = occurrences
+                            .iter()
+                            .zip(is_reference)
+                            .filter_map(|(occurrence, &is_reference)| {
+                                match occurrence.reference {
+                                    query::Reference::Symbol(ref symbol) if is_reference => {
+                                        Some(symbol.clone())
+                                    }
+                                    _ => None,
+                                }
+                            })
+                            .collect();
+                        let conflict =
+                            query::rename_conflict(&module.expr, &renamed, &change.new_name);
+                        if let Some(span) = conflict {
+                            let position = try!(module.byte_span_to_range(encoding, span)).start;
+                            return Err(ServerError::invalid_params(format!(
+                                "Renaming `{}` to `{}` would change what the identifier at \
+                                 {}:{} of `{}` refers to",
+                                old_name,
+                                change.new_name,
+                                position.line + 1,
+                                position.character + 1,
+                                uri
+                            )));
+                        }
+                        let edits = query::rename_edits(
+                            &module.source_string,
+                            occurrences,
+                            is_reference,
+                            &change.new_name,
+                        );
+                        if edits.is_empty() {
+                            return Ok(());
+                        }
+                        let edits = try!(
+                            edits
+                                .into_iter()
+                                .map(|(span, new_text)| {
+                                    Ok(TextEdit {
+                                        range: try!(module.byte_span_to_range(encoding, span)),
+                                        new_text: new_text,
+                                    })
+                                })
+                                .collect::<Result<Vec<_>, ServerError<()>>>()
+                        );
+                        changes.insert(uri, edits);
+                        Ok(())
+                    },
+                ));
+                Ok(WorkspaceEdit { changes: changes })
+            })
+        })().into_future()
+            .boxed()
+    }
+
+    fn invalid_params(&self) -> Option<Self::Error> {
+        None
+    }
+}
+
+struct DocumentSymbolCommand {
+    thread: RootedThread,
+    encoding: Encoding,
+}
+impl LanguageServerCommand<DocumentSymbolParams> for DocumentSymbolCommand {
+    type Output = Vec<SymbolInformation>;
+    type Error = ();
+    fn execute(
+        &self,
+        change: DocumentSymbolParams,
+    ) -> BoxFuture<Vec<SymbolInformation>, ServerError<()>> {
+        let encoding = *self.encoding.lock().unwrap();
+        retrieve_expr(&self.thread, &change.text_document.uri, |module| {
+            query::declarations(&module.expr, &module.source_string)
+                .into_iter()
+                .map(|declaration| {
+                    Ok(SymbolInformation {
+                        name: declaration.name,
+                        kind: declaration.kind,
+                        location: Location {
+                            uri: change.text_document.uri.clone(),
+                            range: try!(module.byte_span_to_range(encoding, declaration.span)),
+                        },
+                        container_name: declaration.container,
+                    })
+                })
+                .collect()
+        }).into_future()
+            .boxed()
+    }
+
+    fn invalid_params(&self) -> Option<Self::Error> {
+        None
+    }
+}
+
+struct WorkspaceSymbolCommand {
+    thread: RootedThread,
+    encoding: Encoding,
+}
+impl LanguageServerCommand<WorkspaceSymbolParams> for WorkspaceSymbolCommand {
+    type Output = Vec<SymbolInformation>;
+    type Error = ();
+    fn execute(
+        &self,
+        change: WorkspaceSymbolParams,
+    ) -> BoxFuture<Vec<SymbolInformation>, ServerError<()>> {
+        (|| -> Result<_, _> {
+            let thread = &self.thread;
+            let encoding = *self.encoding.lock().unwrap();
+            let mut symbols = Vec::new();
+            let mut push_symbols = |name: &str,
+                                    uri: &Url,
+                                    lines: &source::Lines,
+                                    declarations: &[query::Declaration],
+                                    source: &str|
+             -> Result<(), ServerError<()>> {
+                for declaration in declarations {
+                    if !fuzzy_match(&change.query, &declaration.name) {
+                        continue;
+                    }
+                    symbols.push(SymbolInformation {
+                        name: declaration.name.clone(),
+                        kind: declaration.kind,
+                        location: Location {
+                            uri: uri.clone(),
+                            range: try!(byte_span_to_range(
+                                encoding,
+                                lines,
+                                source,
+                                declaration.span,
+                            )),
+                        },
+                        container_name: Some(name.to_string()),
+                    });
+                }
+                Ok(())
+            };
+
+            let loaded = try!(with_modules(thread, |modules| -> Result<_, ServerError<()>> {
+                for (name, module) in modules {
+                    let uri = module.uri.clone().or_else(|| {
+                        module_path(thread, name).and_then(|path| Url::from_file_path(path).ok())
+                    });
+                    // Modules without a file, such as the embedded standard library, have no
+                    // location for their symbols
+                    let uri = match uri {
+                        Some(uri) => uri,
+                        None => continue,
+                    };
+                    try!(push_symbols(
+                        name,
+                        &uri,
+                        &module.lines,
+                        &query::declarations(&module.expr, &module.source_string),
+                        &module.source_string,
+                    ));
+                }
+                Ok(modules.keys().cloned().collect::<Vec<_>>())
+            }));
+
+            // Modules which have not been loaded yet only need to be parsed to find their symbols
+            let token = cancellation_token();
+            for (name, path) in modules_on_disk(thread) {
+                // Parsing every module on disk can take a while the first time
+                if token.is_cancelled() {
+                    break;
+                }
+                if loaded.contains(&name) {
+                    continue;
+                }
+                let module = match disk_module(thread, &name, &path) {
+                    Some(module) => module,
+                    None => continue,
+                };
+                let uri = try!(Url::from_file_path(&path).map_err(|_| {
+                    ServerError::from(format!("Unable to convert `{}` to a url", path.display()))
+                }));
+                try!(push_symbols(
+                    &name,
+                    &uri,
+                    &module.lines,
+                    &module.declarations,
+                    &module.source,
+                ));
+            }
+
+            symbols.sort_by(|l, r| {
+                (&l.name, &l.container_name).cmp(&(&r.name, &r.container_name))
+            });
+            Ok(symbols)
+        })().into_future()
+            .boxed()
+    }
+
+    fn invalid_params(&self) -> Option<Self::Error> {
+        None
+    }
+}
+
+/// Returns `true` if the characters of `query` appears in `name` in order, ignoring case
+fn fuzzy_match(query: &str, name: &str) -> bool {
+    let mut name = name.chars().flat_map(char::to_lowercase);
+    query
+        .chars()
+        .flat_map(char::to_lowercase)
+        .all(|q| name.any(|c| c == q))
+}
+
+/// Collects the gluon files under `dir`, skipping hidden files and directories
+fn glu_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+        let hidden = path.file_name()
+            .and_then(|name| name.to_str())
+            .map_or(false, |name| name.starts_with('.'));
+        if hidden {
+            continue;
+        }
+        if path.is_dir() {
+            glu_files(&path, files);
+        } else if path.extension().map_or(false, |ext| ext == "glu") {
+            files.push(path);
+        }
+    }
+}
+
+/// Returns the files in `paths`, replacing each directory with the gluon files inside it
+fn glu_files_in(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut dir_files = Vec::new();
+            glu_files(path, &mut dir_files);
+            dir_files.sort();
+            files.extend(dir_files);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    files
+}
+
+/// Returns the name and path of each gluon module found under the import paths
+fn modules_on_disk(thread: &Thread) -> Vec<(String, PathBuf)> {
+    let import = thread.get_macros().get("import").expect("Import macro");
+    let import = import
+        .downcast_ref::<CheckImport>()
+        .expect("Check importer");
+    if let Some(ref modules) = *import.disk_modules.lock().unwrap() {
+        return modules.clone();
+    }
+
+    let mut modules = Vec::new();
+    {
+        let paths = import.paths.read().unwrap();
+        for dir in paths.iter().filter_map(|dir| fs::canonicalize(dir).ok()) {
+            let mut files = Vec::new();
+            glu_files(&dir, &mut files);
+            modules.extend(files.into_iter().filter_map(|path| {
+                let name = path.strip_prefix(&dir)
+                    .ok()
+                    .and_then(|name| name.to_str())
+                    .map(filename_to_module);
+                name.map(|name| (name, path.clone()))
+            }));
+        }
+    }
+    *import.disk_modules.lock().unwrap() = Some(modules.clone());
+    modules
+}
+
+/// Forgets the parsed module and module list which the file at `uri` may have changed
+fn invalidate_disk_module(thread: &Thread, uri: &Url) {
+    if let Ok(path) = uri.to_file_path() {
+        let import = thread.get_macros().get("import").expect("Import macro");
+        let import = import
+            .downcast_ref::<CheckImport>()
+            .expect("Check importer");
+        import.invalidate_disk_module(&path);
+    }
+}
+
+/// A module on disk which is not loaded, parsed only to find its declarations
+struct DiskModule {
+    source: String,
+    lines: source::Lines,
+    declarations: Vec<query::Declaration>,
+}
+
+/// Returns the parsed module `name` at `path`, parsing it unless it has been parsed before
+fn disk_module(thread: &Thread, name: &str, path: &Path) -> Option<Arc<DiskModule>> {
+    let import = thread.get_macros().get("import").expect("Import macro");
+    let import = import
+        .downcast_ref::<CheckImport>()
+        .expect("Check importer");
+    if let Some(module) = import.parsed_disk_modules.lock().unwrap().get(path) {
+        return Some(module.clone());
+    }
+
+    let mut source = String::new();
+    if fs::File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut source))
+        .is_err()
+    {
+        return None;
+    }
+    let expr = match Compiler::new().parse_partial_expr(name, &source) {
+        Ok(expr) | Err((Some(expr), _)) => expr,
+        Err((None, _)) => return None,
+    };
+    let module = Arc::new(DiskModule {
+        lines: source::Lines::new(&source),
+        declarations: query::declarations(&expr, &source),
+        source: source,
+    });
+    import
+        .parsed_disk_modules
+        .lock()
+        .unwrap()
+        .insert(path.to_owned(), module.clone());
+    Some(module)
+}
+
+/// Returns the current text of the document at `uri`, which may not have been checked yet, or the
+/// text of the module if the document is not open
+fn document_source(
+    thread: &Thread,
+    documents: &Documents,
+    uri: &Url,
+) -> Result<String, ServerError<()>> {
+    match open_document(documents, uri) {
+        Some(source) => Ok(source),
+        None => retrieve_expr(thread, uri, |module| Ok(module.source_string.clone())),
+    }
+}
+
+struct FormattingCommand {
+    thread: RootedThread,
+    documents: Documents,
+    encoding: Encoding,
+}
+impl LanguageServerCommand<DocumentFormattingParams> for FormattingCommand {
+    type Output = Vec<TextEdit>;
+    type Error = ();
+    fn execute(
+        &self,
+        params: DocumentFormattingParams,
+    ) -> BoxFuture<Vec<TextEdit>, ServerError<()>> {
+        (|| -> Result<_, _> {
+            let encoding = *self.encoding.lock().unwrap();
+            let source = try!(document_source(
+                &self.thread,
+                &self.documents,
+                &params.text_document.uri,
+            ));
+            let formatted = try!(format_source(&source));
+            // Only the changed lines are replaced so that the client can keep its cursor,
+            // folds and undo history for the rest of the document
+            let old = diff::lines(&source);
+            let new = diff::lines(&formatted);
+            Ok(
+                diff_lines(&old, &new)
+                    .iter()
+                    .map(|hunk| hunk_to_edit(encoding, &old, &new, hunk))
+                    .collect(),
+            )
+        })().into_future()
+            .boxed()
+    }
+
+    fn invalid_params(&self) -> Option<Self::Error> {
+        None
+    }
+}
+
+/// Formats `source`, refusing to do so if it can't be parsed as the formatter would otherwise
+/// drop the parts which could not be parsed
+fn format_source(source: &str) -> Result<String, ServerError<()>> {
+    gluon::parser::format_expr(source).map_err(|err| {
+        ServerError {
+            code: ErrorCode::InternalError,
+            message: format!("Unable to format a module with parse errors:\n{}", err),
+            data: None,
+        }
+    })
+}
+
+struct RangeFormattingCommand {
+    thread: RootedThread,
+    documents: Documents,
+    encoding: Encoding,
+}
+impl LanguageServerCommand<DocumentRangeFormattingParams> for RangeFormattingCommand {
+    type Output = Vec<TextEdit>;
+    type Error = ();
+    fn execute(
+        &self,
+        params: DocumentRangeFormattingParams,
+    ) -> BoxFuture<Vec<TextEdit>, ServerError<()>> {
+        (|| -> Result<_, _> {
+            let encoding = *self.encoding.lock().unwrap();
+            let source = try!(document_source(
+                &self.thread,
+                &self.documents,
+                &params.text_document.uri,
+            ));
+            let formatted = try!(format_source(&source));
+            let old_items = try!(top_level_lines(&source));
+            let new_items = try!(top_level_lines(&formatted));
+            if old_items.len() != new_items.len() {
+                return Err(ServerError::from(&"Formatting changed the top level of the module"));
+            }
+
+            let selection = (params.range.start.line as usize, params.range.end.line as usize);
+            let overlaps = |&(first, last): &(usize, usize)| {
+                first <= selection.1 && selection.0 <= last
+            };
+            let (mut first, mut last) = match (
+                old_items.iter().position(&overlaps),
+                old_items.iter().rposition(&overlaps),
+            ) {
+                (Some(first), Some(last)) => (first, last),
+                _ => return Ok(Vec::new()),
+            };
+            // Items which share a line with a selected item must be formatted along with it
+            let shares_line = |items: &[(usize, usize)], i: usize| items[i].1 >= items[i + 1].0;
+            while first > 0 &&
+                (shares_line(&old_items, first - 1) || shares_line(&new_items, first - 1))
+            {
+                first -= 1;
+            }
+            while last + 1 < old_items.len() &&
+                (shares_line(&old_items, last) || shares_line(&new_items, last))
+            {
+                last += 1;
+            }
+
+            let old = diff::lines(&source);
+            let new = diff::lines(&formatted);
+            let old_start = old_items[first].0;
+            let new_start = new_items[first].0;
+            let old_changed = &old[old_start..old_items[last].1 + 1];
+            let new_changed = &new[new_start..new_items[last].1 + 1];
+            Ok(
+                diff_lines(old_changed, new_changed)
+                    .into_iter()
+                    .map(|hunk| {
+                        let hunk = Hunk {
+                            old: old_start + hunk.old.start..old_start + hunk.old.end,
+                            new: new_start + hunk.new.start..new_start + hunk.new.end,
+                        };
+                        hunk_to_edit(encoding, &old, &new, &hunk)
+                    })
+                    .collect(),
+            )
+        })().into_future()
+            .boxed()
+    }
+
+    fn invalid_params(&self) -> Option<Self::Error> {
+        None
+    }
+}
+
+/// Returns the first and last line of each top level binding and expression in `source`
+fn top_level_lines(source: &str) -> Result<Vec<(usize, usize)>, ServerError<()>> {
+    let expr = try!(gluon::parser::parse_expr(&mut Symbols::new(), source));
+    let lines = source::Lines::new(source);
+    query::top_level_spans(&expr)
+        .into_iter()
+        .map(|span| {
+            // Only the lines are used so it does not matter how the characters are counted
+            let range = try!(byte_span_to_range(PositionEncoding::Utf8, &lines, source, span));
+            Ok((range.start.line as usize, range.end.line as usize))
+        })
+        .collect()
+}
+
+/// Returns an edit which replaces the lines `hunk.old` of `old` with the lines `hunk.new` of `new`
+fn hunk_to_edit(encoding: PositionEncoding, old: &[&str], new: &[&str], hunk: &Hunk) -> TextEdit {
+    let end = match old.last() {
+        // A last line without a line ending has no following line for the edit to end on
+        Some(last) if hunk.old.end == old.len() && !last.ends_with('\n') => Position {
+            line: old.len() as u64 - 1,
+            character: encoding.len(last) as u64,
+        },
+        _ => Position {
+            line: hunk.old.end as u64,
+            character: 0,
+        },
+    };
+    TextEdit {
+        range: Range {
+            start: Position {
+                line: hunk.old.start as u64,
+                character: 0,
+            },
+            end: end,
+        },
+        new_text: new[hunk.new.clone()].concat(),
+    }
+}
+
+struct OnTypeFormattingCommand {
+    documents: Documents,
+    encoding: Encoding,
+}
+impl LanguageServerCommand<DocumentOnTypeFormattingParams> for OnTypeFormattingCommand {
+    type Output = Vec<TextEdit>;
+    type Error = ();
+    fn execute(
+        &self,
+        params: DocumentOnTypeFormattingParams,
+    ) -> BoxFuture<Vec<TextEdit>, ServerError<()>> {
+        let encoding = *self.encoding.lock().unwrap();
+        let edits = open_document(&self.documents, &params.text_document.uri)
+            .and_then(|source| {
+                newline_indent(encoding, &source, params.position.line as usize, &params.options)
+            })
+            .into_iter()
+            .collect();
+        Ok(edits).into_future().boxed()
+    }
+
+    fn invalid_params(&self) -> Option<Self::Error> {
+        None
+    }
+}
+
+/// Returns `source` with every character of its string literals, character literals and comments
+/// replaced by a space, so that words inside them are not mistaken for keywords. Newlines are kept
+/// so that the lines still match the lines of `source`, and a literal which is still being typed
+/// ends at the end of its line.
+fn blank_strings_and_comments(source: &str) -> String {
+    enum State {
+        Code,
+        Literal(char),
+        LineComment,
+        BlockComment,
+    }
+    let mut result = String::with_capacity(source.len());
+    let mut state = State::Code;
+    let mut previous = ' ';
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\n' {
+            match state {
+                State::BlockComment => (),
+                _ => state = State::Code,
+            }
+            result.push(c);
+            previous = c;
+            continue;
+        }
+        let code = match state {
+            State::Code => match c {
+                '"' => {
+                    state = State::Literal(c);
+                    false
+                }
+                // `'` continues identifiers such as `x'`
+                '\'' if !(previous.is_alphanumeric() || previous == '_' || previous == '\'') => {
+                    state = State::Literal(c);
+                    false
+                }
+                '/' if chars.peek() == Some(&'/') => {
+                    state = State::LineComment;
+                    false
+                }
+                '/' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    result.push(' ');
+                    state = State::BlockComment;
+                    false
+                }
+                _ => true,
+            },
+            State::Literal(quote) => {
+                if c == '\\' && chars.peek().map_or(false, |&next| next != '\n') {
+                    chars.next();
+                    result.push(' ');
+                } else if c == quote {
+                    state = State::Code;
+                }
+                false
+            }
+            State::LineComment => false,
+            State::BlockComment => {
+                if c == '*' && chars.peek() == Some(&'/') {
+                    chars.next();
+                    result.push(' ');
+                    state = State::Code;
+                }
+                false
+            }
+        };
+        result.push(if code { c } else { ' ' });
+        previous = c;
+    }
+    result
+}
+
+/// Returns an edit which indents the line `line` of `source` after a newline was inserted before
+/// it, or `None` if the line does not follow a token which starts an indented block
+fn newline_indent(
+    encoding: PositionEncoding,
+    source: &str,
+    line: usize,
+    options: &FormattingOptions,
+) -> Option<TextEdit> {
+    fn is_ident_char(c: char) -> bool {
+        c.is_alphanumeric() || c == '_' || c == '\''
+    }
+    fn ends_with_word(line: &str, word: &str) -> bool {
+        line.ends_with(word) &&
+            !line[..line.len() - word.len()]
+                .chars()
+                .next_back()
+                .map_or(false, is_ident_char)
+    }
+    fn ends_with_equals(line: &str) -> bool {
+        line.ends_with('=') &&
+            !line[..line.len() - 1]
+                .chars()
+                .next_back()
+                .map_or(false, |c| "!#$%&*+./<=>?@\\^|-~:".contains(c))
+    }
+    fn indentation(line: &str) -> &str {
+        &line[..line.len() - line.trim_left().len()]
+    }
+    /// Returns the line of the `let` which is closed by an `in` at the end of `lines`
+    fn let_line(lines: &[&str]) -> Option<usize> {
+        let mut depth = 0;
+        for (i, line) in lines.iter().enumerate().rev() {
+            for word in line.rsplit(|c: char| !is_ident_char(c)) {
+                match word {
+                    "in" => depth += 1,
+                    "let" => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(i);
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+        None
+    }
+
+    let lines: Vec<&str> = source.lines().collect();
+    if line == 0 || line > lines.len() {
+        return None;
+    }
+    // Keywords are only searched for in the code, while the indentation is taken from `lines`
+    let code = blank_strings_and_comments(source);
+    let code_lines: Vec<&str> = code.lines().collect();
+    let previous = code_lines[line - 1].trim_right();
+    let indent = if ends_with_word(previous, "in") {
+        match let_line(&code_lines[..line]) {
+            Some(let_line) => indentation(lines[let_line]).to_string(),
+            None => return None,
+        }
+    } else if previous.ends_with("->") || ends_with_equals(previous) ||
+        ends_with_word(previous, "then") || ends_with_word(previous, "else")
+    {
+        let unit = if options.insert_spaces {
+            " ".repeat(options.tab_size as usize)
+        } else {
+            "\t".to_string()
+        };
+        format!("{}{}", indentation(lines[line - 1]), unit)
+    } else {
+        return None;
+    };
+
+    let current_indent = indentation(lines.get(line).cloned().unwrap_or(""));
+    if current_indent == indent {
+        return None;
+    }
+    Some(TextEdit {
+        range: Range {
+            start: Position {
+                line: line as u64,
+                character: 0,
+            },
+            end: Position {
+                line: line as u64,
+                character: encoding.len(current_indent) as u64,
+            },
+        },
+        new_text: indent,
+    })
+}
+
+fn location_to_position(loc: &pos::Location) -> Position {
+    Position {
+        line: loc.line.to_usize() as u64,
+        character: loc.column.to_usize() as u64,
+    }
+}
+fn span_to_range(span: &Span<pos::Location>) -> Range {
+    Range {
+        start: location_to_position(&span.start),
+        end: location_to_position(&span.end),
+    }
+}
+
+fn byte_pos_to_position(
+    encoding: PositionEncoding,
+    lines: &source::Lines,
+    source: &str,
+    pos: BytePos,
+) -> Result<Position, ServerError<()>> {
+    let location = try!(lines.location(pos).ok_or_else(|| {
+        ServerError::from(&"Unable to translate index to location")
+    }));
+    let line_start = pos.to_usize() - location.column.to_usize();
+    let character = source
+        .get(line_start..pos.to_usize())
+        .map_or(location.column.to_usize(), |text| encoding.len(text));
+    Ok(Position {
+        line: location.line.to_usize() as u64,
+        character: character as u64,
+    })
+}
+
+fn byte_span_to_range(
+    encoding: PositionEncoding,
+    lines: &source::Lines,
+    source: &str,
+    span: Span<BytePos>,
+) -> Result<Range, ServerError<()>> {
+    Ok(Range {
+        start: try!(byte_pos_to_position(encoding, lines, source, span.start)),
+        end: try!(byte_pos_to_position(encoding, lines, source, span.end)),
+    })
+}
+
+/// The current text of each document which the client has opened
+type Documents = Arc<Mutex<FnvMap<Url, String>>>;
+
+thread_local! {
+    /// The document which the request being handled on this thread refers to, along with its text
+    /// when the request was received or `None` if it was not open
+    static DOCUMENT_SNAPSHOT: RefCell<Option<(Url, Option<String>)>> = RefCell::new(None);
+}
+
+/// Returns the document which the request `json` refers to along with its current text
+fn document_snapshot(documents: &Documents, json: &str) -> Option<(Url, Option<String>)> {
+    let request: Value = match serde_json::from_str(json) {
+        Ok(request) => request,
+        Err(_) => return None,
+    };
+    request["params"]["textDocument"]["uri"]
+        .as_str()
+        .and_then(|uri| Url::parse(uri).ok())
+        .map(|uri| {
+            let text = documents.lock().unwrap().get(&uri).cloned();
+            (uri, text)
+        })
+}
+
+/// Runs `f` with `snapshot` as the document of the request which is handled on this thread
+fn with_document_snapshot<F, R>(snapshot: Option<(Url, Option<String>)>, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let previous = DOCUMENT_SNAPSHOT.with(|current| current.replace(snapshot));
+    let result = f();
+    DOCUMENT_SNAPSHOT.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+/// Returns the text of the open document at `uri`. Requests see the text from when they were
+/// received, as the notifications which are handled while they run may change it.
+fn open_document(documents: &Documents, uri: &Url) -> Option<String> {
+    let snapshot = DOCUMENT_SNAPSHOT.with(|snapshot| match *snapshot.borrow() {
+        Some((ref snapshot_uri, ref text)) if snapshot_uri == uri => Some(text.clone()),
+        _ => None,
+    });
+    snapshot.unwrap_or_else(|| documents.lock().unwrap().get(uri).cloned())
+}
+
+struct WorkspaceDidChangeConfiguration {
+    client: ClientSender,
+    thread: RootedThread,
+    settings: Arc<Mutex<Settings>>,
+    documents: Documents,
+    work_queue: Arc<UniqueQueue<Url, Option<String>>>,
+}
+impl LanguageServerNotification<DidChangeConfigurationParams> for WorkspaceDidChangeConfiguration {
+    fn execute(&self, change: DidChangeConfigurationParams) {
+        if !update_settings(&self.client, &self.thread, &self.settings, &change.settings) {
+            return;
+        }
+        // The import paths and the number of problems change the diagnostics of every document
+        for (uri, source) in self.documents.lock().unwrap().iter() {
+            self.work_queue.add_work(uri.clone(), Some(source.clone()));
+        }
+    }
+}
+
+struct TextDocumentDidOpen {
+    client: ClientSender,
+    thread: RootedThread,
+    settings: Arc<Mutex<Settings>>,
+    documents: Documents,
+    work_queue: Arc<UniqueQueue<Url, Option<String>>>,
+    encoding: Encoding,
+}
+impl LanguageServerNotification<DidOpenTextDocumentParams> for TextDocumentDidOpen {
+    fn execute(&self, change: DidOpenTextDocumentParams) {
+        self.documents.lock().unwrap().insert(
+            change.text_document.uri.clone(),
+            change.text_document.text.clone(),
+        );
+        let settings = self.settings.lock().unwrap().clone();
+        let encoding = *self.encoding.lock().unwrap();
+        run_diagnostics(
+            &self.client,
+            &self.thread,
+            encoding,
+            &settings,
+            &change.text_document.uri,
+            &change.text_document.text,
+        );
+        queue_importers(
+            &self.thread,
+            &self.documents,
+            &self.work_queue,
+            &change.text_document.uri,
+        );
+    }
+}
+
+struct TextDocumentDidChange {
+    client: ClientSender,
+    thread: RootedThread,
+    documents: Documents,
+    work_queue: Arc<UniqueQueue<Url, Option<String>>>,
+    encoding: Encoding,
+}
+impl LanguageServerNotification<DidChangeTextDocumentParams> for TextDocumentDidChange {
+    fn execute(&self, change: DidChangeTextDocumentParams) {
+        let encoding = *self.encoding.lock().unwrap();
+        let uri = &change.text_document.uri;
+        let source = {
+            let mut documents = self.documents.lock().unwrap();
+            // The changes are applied to a copy so that a change which fails does not leave the
+            // document with only some of the changes
+            let mut source = documents.get(uri).cloned().unwrap_or_default();
+            for content_change in change.content_changes {
+                if let Err(err) = apply_change(encoding, &mut source, content_change) {
+                    // The document no longer matches the client's so it is dropped until the
+                    // client sends its full text again
+                    documents.remove(uri);
+                    self.work_queue.remove_work(uri);
+                    log_message!(
+                        self.client,
+                        "Unable to apply change to `{}`, the document is out of sync: {}",
+                        uri,
+                        err.message
+                    );
+                    return;
+                }
+            }
+            documents.insert(uri.clone(), source.clone());
+            source
+        };
+        self.work_queue.add_work(change.text_document.uri.clone(), Some(source));
+        queue_importers(
+            &self.thread,
+            &self.documents,
+            &self.work_queue,
+            &change.text_document.uri,
+        );
+    }
+}
+
+struct TextDocumentDidSave {
+    client: ClientSender,
+    thread: RootedThread,
+    documents: Documents,
+    work_queue: Arc<UniqueQueue<Url, Option<String>>>,
+}
+impl LanguageServerNotification<DidSaveTextDocumentParams> for TextDocumentDidSave {
+    fn execute(&self, change: DidSaveTextDocumentParams) {
+        let uri = change.text_document.uri;
+        invalidate_disk_module(&self.thread, &uri);
+        let source = self.documents.lock().unwrap().get(&uri).cloned();
+        let source = match source.or_else(|| read_file(&uri)) {
+            Some(source) => source,
+            None => {
+                log_message!(self.client, "Unable to read saved document `{}`", uri);
+                return;
+            }
+        };
+        // The saved module is checked before its importers as the queue is processed in order
+        self.work_queue.add_work(uri.clone(), Some(source));
+        queue_importers(&self.thread, &self.documents, &self.work_queue, &uri);
+    }
+}
+
+struct TextDocumentDidClose {
+    client: ClientSender,
+    thread: RootedThread,
+    documents: Documents,
+    work_queue: Arc<UniqueQueue<Url, Option<String>>>,
+}
+impl LanguageServerNotification<DidCloseTextDocumentParams> for TextDocumentDidClose {
+    fn execute(&self, change: DidCloseTextDocumentParams) {
+        let uri = change.text_document.uri;
+        self.documents.lock().unwrap().remove(&uri);
+        self.work_queue.remove_work(&uri);
+
+        reload_from_disk(&self.thread, &uri);
+        publish_diagnostics(&self.client, uri.clone(), vec![]);
+        queue_importers(&self.thread, &self.documents, &self.work_queue, &uri);
+    }
+}
+
+struct WorkspaceDidChangeWatchedFiles {
+    thread: RootedThread,
+    documents: Documents,
+    work_queue: Arc<UniqueQueue<Url, Option<String>>>,
+}
+impl LanguageServerNotification<DidChangeWatchedFilesParams> for WorkspaceDidChangeWatchedFiles {
+    fn execute(&self, change: DidChangeWatchedFilesParams) {
+        let mut created = false;
+        for event in change.changes {
+            let uri = event.uri;
+            if !uri.path().ends_with(".glu") {
+                continue;
+            }
+            invalidate_disk_module(&self.thread, &uri);
+            // Open documents are kept in sync by the client instead
+            if self.documents.lock().unwrap().contains_key(&uri) {
+                continue;
+            }
+            let name = filename_to_module(&strip_file_prefix_with_thread(&self.thread, &uri));
+            if with_modules(&self.thread, |modules| modules.contains_key(&name)) {
+                debug!("Reloading `{}` from disk", name);
+                reload_from_disk(&self.thread, &uri);
+                queue_importers(&self.thread, &self.documents, &self.work_queue, &uri);
+            } else if event.typ == FileChangeType::Created {
+                created = true;
+            }
+        }
+        // A new file may be a module which an open document failed to import
+        if created {
+            for (uri, source) in self.documents.lock().unwrap().iter() {
+                self.work_queue.add_work(uri.clone(), Some(source.clone()));
+            }
+        }
+    }
+}
+
+/// Replaces the module at `uri` with the version on disk, the same as if it had been imported, or
+/// drops it if the file does not exist. Modules which could not be reloaded are marked as stale so
+/// that `import!` reports their errors instead of using their old globals.
+fn reload_from_disk(thread: &Thread, uri: &Url) {
+    let name = filename_to_module(&strip_file_prefix_with_thread(thread, uri));
+    let import = thread.get_macros().get("import").expect("Import macro");
+    let import = import
+        .downcast_ref::<CheckImport>()
+        .expect("Check importer");
+    match read_file(uri) {
+        Some(source) => {
+            if let Err(err) = typecheck(thread, uri, &source) {
+                debug!("`{}` has errors on disk: {}", uri, err);
+                import.stale.lock().unwrap().insert(name.clone());
+            }
+            with_modules_mut(thread, |modules| if let Some(module) = modules.get_mut(&name) {
+                module.uri = None;
+            });
+        }
+        None => {
+            with_modules_mut(thread, |modules| modules.remove(&name));
+            import.stale.lock().unwrap().insert(name);
+        }
     }
 }
 
-fn location_to_position(loc: &pos::Location) -> Position {
-    Position {
-        line: loc.line.to_usize() as u64,
-        character: loc.column.to_usize() as u64,
+fn read_file(uri: &Url) -> Option<String> {
+    let path = match uri.to_file_path() {
+        Ok(path) => path,
+        Err(()) => return None,
+    };
+    let mut source = String::new();
+    match fs::File::open(path).and_then(|mut file| file.read_to_string(&mut source)) {
+        Ok(_) => Some(source),
+        Err(_) => None,
     }
 }
-fn span_to_range(span: &Span<pos::Location>) -> Range {
-    Range {
-        start: location_to_position(&span.start),
-        end: location_to_position(&span.end),
+
+/// Queues the modules which transitively import the module at `uri` so that they are checked
+/// against its current state. Open documents are queued with their text while other modules are
+/// reloaded from disk, so that a change reaches the modules which import them as well.
+fn queue_importers(
+    thread: &Thread,
+    documents: &Documents,
+    work_queue: &UniqueQueue<Url, Option<String>>,
+    uri: &Url,
+) {
+    let module_name = filename_to_module(&strip_file_prefix_with_thread(thread, uri));
+    let mut documents: FnvMap<_, _> = documents
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(document_uri, source)| {
+            let name = filename_to_module(&strip_file_prefix_with_thread(thread, document_uri));
+            (name, (document_uri.clone(), source.clone()))
+        })
+        .collect();
+    let importers = with_modules(thread, |modules| {
+        let graph: Vec<_> = modules
+            .iter()
+            .map(|(name, module)| (&name[..], &module.imports[..]))
+            .collect();
+        importers(&graph, &module_name)
+    });
+    for importer in importers {
+        match documents.remove(&importer) {
+            Some((document_uri, source)) => work_queue.add_work_last(document_uri, Some(source)),
+            None => {
+                // Modules which were not loaded from a file, such as the standard library, can't
+                // import the changed module
+                let uri = match module_name_to_url(thread, &importer) {
+                    Ok(uri) => uri,
+                    Err(_) => continue,
+                };
+                if uri.to_file_path().map_or(false, |path| path.is_file()) {
+                    work_queue.add_work_last(uri, None);
+                }
+            }
+        }
     }
 }
 
-struct TextDocumentDidOpen(RootedThread);
-impl LanguageServerNotification<DidOpenTextDocumentParams> for TextDocumentDidOpen {
-    fn execute(&self, change: DidOpenTextDocumentParams) {
-        run_diagnostics(
-            &self.0,
-            &change.text_document.uri,
-            &change.text_document.text,
-        );
+/// Returns the modules in `graph` which transitively import `module_name`, ordered so that every
+/// module comes after the modules it imports
+fn importers(graph: &[(&str, &[String])], module_name: &str) -> Vec<String> {
+    fn visit<'a>(
+        graph: &[(&'a str, &[String])],
+        module_name: &str,
+        visited: &mut FnvSet<&'a str>,
+        order: &mut Vec<String>,
+    ) {
+        for &(name, imports) in graph {
+            if imports.iter().any(|import| import == module_name) && visited.insert(name) {
+                visit(graph, name, visited, order);
+                order.push(name.to_string());
+            }
+        }
     }
+    let mut visited = FnvSet::default();
+    // Guards against cycles leading back to `module_name`
+    visited.insert(module_name);
+    let mut order = Vec::new();
+    visit(graph, module_name, &mut visited, &mut order);
+    order.reverse();
+    order
 }
 
-struct TextDocumentDidChange(Arc<UniqueQueue<Url, String>>);
-impl LanguageServerNotification<DidChangeTextDocumentParams> for TextDocumentDidChange {
-    fn execute(&self, mut change: DidChangeTextDocumentParams) {
-        use std::mem::replace;
-        self.0.add_work(
-            change.text_document.uri,
-            replace(&mut change.content_changes[0].text, String::new()),
-        )
+/// Applies `change` to `source`. Changes without a range replace the entire document.
+fn apply_change(
+    encoding: PositionEncoding,
+    source: &mut String,
+    change: TextDocumentContentChangeEvent,
+) -> Result<(), ServerError<()>> {
+    match change.range {
+        Some(range) => {
+            let (start, end) = {
+                let lines = source::Lines::new(source);
+                (
+                    position_to_byte_pos(encoding, &lines, source, &range.start)?.to_usize(),
+                    position_to_byte_pos(encoding, &lines, source, &range.end)?.to_usize(),
+                )
+            };
+            if start > end || end > source.len() || !source.is_char_boundary(start) ||
+                !source.is_char_boundary(end)
+            {
+                return Err(ServerError::from(format!("Invalid range {:?}", range)));
+            }
+            source.drain(start..end);
+            source.insert_str(start, &change.text);
+        }
+        None => *source = change.text,
     }
+    Ok(())
 }
 
 fn strip_file_prefix_with_thread(thread: &Thread, url: &Url) -> String {
     let import = thread.get_macros().get("import").expect("Import macro");
     let import = import
-        .downcast_ref::<Import<CheckImporter>>()
+        .downcast_ref::<CheckImport>()
         .expect("Check importer");
     let paths = import.paths.read().unwrap();
     strip_file_prefix(&paths, url).unwrap_or_else(|err| panic!("{}", err))
@@ -409,12 +2800,16 @@ pub fn strip_file_prefix(paths: &[PathBuf], url: &Url) -> Result<String, Box<Std
     ))
 }
 
-fn typecheck(thread: &Thread, filename: &Url, fileinput: &str) -> GluonResult<()> {
+/// Parses and typechecks `fileinput` as the module `name` without loading it. Returns the
+/// expression and its type along with the errors which were found, unless the source could not be
+/// parsed at all.
+fn check_expr(
+    thread: &Thread,
+    name: &str,
+    fileinput: &str,
+) -> GluonResult<(SpannedExpr<Symbol>, ArcType, Errors<GluonError>)> {
     use gluon::compiler_pipeline::*;
 
-    let filename = strip_file_prefix_with_thread(thread, filename);
-    let name = filename_to_module(&filename);
-    debug!("Loading: `{}`", name);
     let mut errors = Errors::new();
     let mut compiler = Compiler::new();
     // The parser may find parse errors but still produce an expression
@@ -441,6 +2836,14 @@ fn typecheck(thread: &Thread, filename: &Url, fileinput: &str) -> GluonResult<()
             expr.env_type_of(&*thread.global_env().get_env())
         }
     };
+    Ok((expr, typ, errors))
+}
+
+fn typecheck(thread: &Thread, uri: &Url, fileinput: &str) -> GluonResult<()> {
+    let filename = strip_file_prefix_with_thread(thread, uri);
+    let name = filename_to_module(&filename);
+    debug!("Loading: `{}`", name);
+    let (expr, typ, errors) = try!(check_expr(thread, &name, fileinput));
     let metadata = Metadata::default();
     try!(
         thread
@@ -449,19 +2852,13 @@ fn typecheck(thread: &Thread, filename: &Url, fileinput: &str) -> GluonResult<()
     );
     let import = thread.get_macros().get("import").expect("Import macro");
     let import = import
-        .downcast_ref::<Import<CheckImporter>>()
+        .downcast_ref::<CheckImport>()
         .expect("Check importer");
+    import.stale.lock().unwrap().remove(&name);
     let mut importer = import.importer.0.lock().unwrap();
 
-    let lines = source::Lines::new(fileinput);
-    importer.insert(
-        name.into(),
-        Module {
-            lines: lines,
-            expr: expr,
-            source_string: fileinput.into(),
-        },
-    );
+    let module = Module::new(&importer, &name, expr, fileinput, Some(uri.clone()));
+    importer.insert(name.into(), module);
     if errors.is_empty() {
         Ok(())
     } else {
@@ -478,6 +2875,8 @@ struct Entry<K, V> {
 struct UniqueQueue<K, V> {
     queue: Mutex<VecDeque<Entry<K, V>>>,
     new_work: Condvar,
+    /// Set once no more work will be processed
+    closed: AtomicBool,
 }
 
 impl<K, V> UniqueQueue<K, V>
@@ -497,81 +2896,216 @@ where
         });
         self.new_work.notify_one();
     }
+
+    /// Adds work behind every other item, moving the previous item for this key if one exists
+    fn add_work_last(&self, key: K, value: V) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.retain(|entry| entry.key != key);
+        queue.push_back(Entry {
+            key: key,
+            value: value,
+        });
+        self.new_work.notify_one();
+    }
+
+    fn remove_work(&self, key: &K) {
+        self.queue.lock().unwrap().retain(|entry| entry.key != *key);
+    }
+
+    /// Stops the processing of the queue, dropping any remaining work
+    fn close(&self) {
+        let _queue = self.queue.lock().unwrap();
+        self.closed.store(true, atomic::Ordering::SeqCst);
+        self.new_work.notify_all();
+    }
 }
 
 struct DiagnosticProcessor {
+    client: ClientSender,
     thread: RootedThread,
-    work_queue: Arc<UniqueQueue<Url, String>>,
+    settings: Arc<Mutex<Settings>>,
+    /// The text of the open documents to check, or `None` for modules which are reloaded from disk
+    work_queue: Arc<UniqueQueue<Url, Option<String>>>,
+    encoding: Encoding,
 }
 
 impl DiagnosticProcessor {
     fn run(&self) {
         let mut work_queue = self.work_queue.queue.lock().unwrap();
         loop {
+            let debounce = self.settings.lock().unwrap().debounce;
+            if !work_queue.is_empty() && debounce != 0 {
+                // Let further changes replace the queued work before checking it
+                drop(work_queue);
+                thread::sleep(Duration::from_millis(debounce));
+                work_queue = self.work_queue.queue.lock().unwrap();
+            }
             while let Some(entry) = work_queue.pop_front() {
+                if self.work_queue.closed.load(atomic::Ordering::SeqCst) {
+                    return;
+                }
                 // Don't block the producers while we run diagnostics
                 drop(work_queue);
-                run_diagnostics(&self.thread, &entry.key, &entry.value);
+                match entry.value {
+                    Some(ref source) => {
+                        let settings = self.settings.lock().unwrap().clone();
+                        let encoding = *self.encoding.lock().unwrap();
+                        run_diagnostics(
+                            &self.client,
+                            &self.thread,
+                            encoding,
+                            &settings,
+                            &entry.key,
+                            source,
+                        );
+                    }
+                    // Modules which are not open only have their state updated
+                    None => reload_from_disk(&self.thread, &entry.key),
+                }
                 work_queue = self.work_queue.queue.lock().unwrap();
             }
+            // `close` needs the lock so it can't be missed between this check and the wait
+            if self.work_queue.closed.load(atomic::Ordering::SeqCst) {
+                return;
+            }
             work_queue = self.work_queue.new_work.wait(work_queue).unwrap();
         }
     }
 }
 
+fn module_name_to_file_(s: &str) -> Result<Url, Box<StdError>> {
+    let mut result = s.replace(".", "/");
+    result.push_str(".glu");
+    let path = fs::canonicalize(&*result).or_else(
+        |err| match env::current_dir() {
+            Ok(path) => Ok(path.join(result)),
+            Err(_) => Err(err),
+        },
+    )?;
+    Ok(url::Url::from_file_path(path)
+        .or_else(|_| url::Url::from_file_path(s))
+        .map_err(|_| {
+            format!("Unable to convert module name to a url: `{}`", s)
+        })?)
+}
+
+/// Returns the path of the file which `Import` loads the module `name` from, or `None` if there
+/// is no such file, as for the standard library modules which are embedded in the binary
+fn module_path(thread: &Thread, name: &str) -> Option<PathBuf> {
+    let import = thread.get_macros().get("import").expect("Import macro");
+    let import = import
+        .downcast_ref::<CheckImport>()
+        .expect("Check importer");
+    let filename = import.filenames.lock().unwrap().get(name).cloned();
+    let filename = filename.unwrap_or_else(|| {
+        let mut filename = name.replace(".", "/");
+        filename.push_str(".glu");
+        filename
+    });
+    let paths = import.paths.read().unwrap();
+    paths
+        .iter()
+        .filter_map(|path| fs::canonicalize(path.join(&filename)).ok())
+        .next()
+}
+
+/// Returns the url of the file which `Import` loads the module `name` from
+fn module_name_to_url(thread: &Thread, name: &str) -> Result<Url, ServerError<()>> {
+    match module_path(thread, name) {
+        Some(path) => Url::from_file_path(path).map_err(|_| {
+            ServerError::from(format!("Unable to convert module name to a url: `{}`", name))
+        }),
+        None => module_name_to_file_(name).map_err(|err| ServerError::from(err.to_string())),
+    }
+}
+
+/// Returns the url of the module `name`, preferring the url the client opened it with
+fn module_uri(
+    thread: &Thread,
+    modules: &FnvMap<String, Module>,
+    name: &str,
+) -> Result<Url, ServerError<()>> {
+    match modules.get(name).and_then(|module| module.uri.clone()) {
+        Some(uri) => Ok(uri),
+        None => module_name_to_url(thread, name),
+    }
+}
+
 fn create_diagnostics(
+    thread: &Thread,
+    encoding: PositionEncoding,
     diagnostics: &mut BTreeMap<Url, Vec<Diagnostic>>,
     filename: &Url,
+    fileinput: &str,
     err: GluonError,
 ) {
-    fn into_diagnostic<T>(err: pos::Spanned<T, pos::Location>) -> Diagnostic
+    fn into_diagnostic<T>(
+        encoding: PositionEncoding,
+        source: &Option<(source::Lines, String)>,
+        err: pos::Spanned<T, pos::Location>,
+    ) -> Diagnostic
     where
         T: fmt::Display,
     {
+        let span = Span::new(err.span.start.absolute, err.span.end.absolute);
+        let range = source
+            .as_ref()
+            .and_then(|&(ref lines, ref source)| {
+                byte_span_to_range(encoding, lines, source, span).ok()
+            })
+            .unwrap_or_else(|| span_to_range(&err.span));
         Diagnostic {
             message: format!("{}", err.value),
             severity: Some(DiagnosticSeverity::Error),
-            range: span_to_range(&err.span),
+            range: range,
             ..Diagnostic::default()
         }
     }
 
-    fn module_name_to_file_(s: &str) -> Result<Url, Box<StdError>> {
-        let mut result = s.replace(".", "/");
-        result.push_str(".glu");
-        let path = fs::canonicalize(&*result).or_else(
-            |err| match env::current_dir() {
-                Ok(path) => Ok(path.join(result)),
-                Err(_) => Err(err),
-            },
-        )?;
-        Ok(url::Url::from_file_path(path)
-            .or_else(|_| url::Url::from_file_path(s))
-            .map_err(|_| {
-                format!("Unable to convert module name to a url: `{}`", s)
-            })?)
-    }
-
-    fn module_name_to_file(s: &str) -> Url {
-        module_name_to_file_(s).unwrap()
-    }
+    // Positions are counted in the source of the module which the error was found in
+    let checked_module = filename_to_module(&strip_file_prefix_with_thread(thread, filename));
+    let error_uri = |name: &str| if name == checked_module {
+        filename.clone()
+    } else {
+        with_modules(thread, |modules| module_uri(thread, modules, name))
+            .unwrap_or_else(|_| filename.clone())
+    };
+    let error_source = |name: &str| if name == checked_module {
+        Some((source::Lines::new(fileinput), fileinput.to_string()))
+    } else {
+        with_modules(thread, |modules| {
+            modules
+                .get(name)
+                .map(|module| (module.lines.clone(), module.source_string.clone()))
+        })
+    };
 
     match err {
         GluonError::Typecheck(err) => {
+            let source = error_source(&err.source_name);
             diagnostics
-                .entry(module_name_to_file(&err.source_name))
+                .entry(error_uri(&err.source_name))
                 .or_insert(Vec::new())
-                .extend(err.errors().into_iter().map(into_diagnostic))
+                .extend(
+                    err.errors()
+                        .into_iter()
+                        .map(|err| into_diagnostic(encoding, &source, err)),
+                )
         }
         GluonError::Parse(err) => {
+            let source = error_source(&err.source_name);
             diagnostics
-                .entry(module_name_to_file(&err.source_name))
+                .entry(error_uri(&err.source_name))
                 .or_insert(Vec::new())
-                .extend(err.errors().into_iter().map(into_diagnostic))
+                .extend(
+                    err.errors()
+                        .into_iter()
+                        .map(|err| into_diagnostic(encoding, &source, err)),
+                )
         }
         GluonError::Multiple(errors) => {
             for err in errors {
-                create_diagnostics(diagnostics, filename, err);
+                create_diagnostics(thread, encoding, diagnostics, filename, fileinput, err);
             }
         }
         err => {
@@ -587,86 +3121,158 @@ fn create_diagnostics(
     }
 }
 
-fn run_diagnostics(thread: &Thread, filename: &Url, fileinput: &str) {
+fn run_diagnostics(
+    client: &ClientSender,
+    thread: &Thread,
+    encoding: PositionEncoding,
+    settings: &Settings,
+    filename: &Url,
+    fileinput: &str,
+) {
     info!("Running diagnostics on {}", filename);
 
     let diagnostics = match typecheck(thread, filename, fileinput) {
         Ok(_) => Some((filename.clone(), vec![])).into_iter().collect(),
         Err(err) => {
             let mut diagnostics = BTreeMap::new();
-            create_diagnostics(&mut diagnostics, filename, err);
+            create_diagnostics(thread, encoding, &mut diagnostics, filename, fileinput, err);
             diagnostics
         }
     };
-    for (source_name, diagnostic) in diagnostics {
-        let r = format!(
-            r#"{{
-                            "jsonrpc": "2.0",
-                            "method": "textDocument/publishDiagnostics",
-                            "params": {}
-                        }}"#,
-            serde_json::to_value(&PublishDiagnosticsParams {
-                uri: source_name,
-                diagnostics: diagnostic,
-            }).unwrap()
-        );
-        print!("Content-Length: {}\r\n\r\n{}", r.len(), r);
+    for (source_name, mut diagnostic) in diagnostics {
+        diagnostic.truncate(settings.max_number_of_problems);
+        publish_diagnostics(client, source_name, diagnostic);
     }
 }
 
+fn publish_diagnostics(client: &ClientSender, uri: Url, diagnostics: Vec<Diagnostic>) {
+    client.send_notification(
+        "textDocument/publishDiagnostics",
+        &PublishDiagnosticsParams {
+            uri: uri,
+            diagnostics: diagnostics,
+        },
+    );
+}
+
+/// The number of threads which handle requests
+const REQUEST_THREADS: usize = 4;
+
+/// Returns the message which a thread panicked with
+fn panic_message(err: &(Any + Send)) -> &str {
+    err.downcast_ref::<&'static str>()
+        .cloned()
+        .or_else(|| err.downcast_ref::<String>().map(|s| &s[..]))
+        .unwrap_or("Any")
+}
+
 pub fn run() {
     ::env_logger::init().unwrap();
 
+    start_server(BufReader::new(io::stdin()), io::stdout());
+}
+
+/// Runs a language server which reads messages from `input` and writes to `output`. Returns once
+/// the client has exited or closed `input`.
+pub fn start_server<R, W>(input: R, output: W)
+where
+    R: BufRead + Send + 'static,
+    W: Write + Send + 'static,
+{
     let thread = new_vm();
+    let writer = MessageWriter::new(output);
+    let client = writer.sender();
+    let documents = Documents::default();
+    let settings = Arc::new(Mutex::new(Settings::default()));
+    let snippet_support = Arc::new(AtomicBool::new(false));
+    let encoding = Arc::new(Mutex::new(PositionEncoding::Utf16));
     let work_queue = Arc::new(UniqueQueue {
         queue: Mutex::new(VecDeque::new()),
         new_work: Condvar::new(),
+        closed: AtomicBool::new(false),
     });
 
     let handle = {
+        let client = client.clone();
+        let settings = settings.clone();
+        let encoding = encoding.clone();
         let work_queue = work_queue.clone();
         let thread = thread.clone();
         thread::spawn(move || {
 
-            let import = Import::new(CheckImporter::new());
+            let import = CheckImport::new();
             thread.get_macros().insert("import".into(), import);
 
             let mut io = IoHandler::new();
-            io.add_async_method("initialize", ServerCommand::new(Initialize(thread.clone())));
+            io.add_async_method(
+                "initialize",
+                ServerCommand::new(Initialize {
+                    client: client.clone(),
+                    thread: thread.clone(),
+                    settings: settings.clone(),
+                    snippet_support: snippet_support.clone(),
+                    encoding: encoding.clone(),
+                }),
+            );
             io.add_async_method(
                 "textDocument/completion",
-                ServerCommand::new(Completion(thread.clone())),
+                ServerCommand::new(Completion {
+                    thread: thread.clone(),
+                    documents: documents.clone(),
+                    snippet_support: snippet_support.clone(),
+                    encoding: encoding.clone(),
+                }),
             );
 
             {
+                let client = client.clone();
                 let thread = thread.clone();
-                let resolve = move |mut item: CompletionItem| -> BoxFuture<CompletionItem, _> {
-                    let data: CompletionData = serde_json::from_value(item.data.clone().unwrap())
+                let encoding = encoding.clone();
+                // The item is kept as JSON so that kinds unknown to `languageserver_types` are
+                // passed back unchanged
+                let resolve = move |mut item: Value| -> BoxFuture<Value, _> {
+                    if let Ok(data) =
+                        serde_json::from_value::<ModuleCompletionData>(item["data"].clone())
+                    {
+                        return module_type(&thread, &data.module)
+                            .map(|typ| {
+                                item["detail"] = Value::from(typ.to_string());
+                                item
+                            })
+                            .into_future()
+                            .boxed();
+                    }
+                    let data: CompletionData = serde_json::from_value(item["data"].clone())
                         .expect("CompletionData");
+                    let label = item["label"].as_str().unwrap_or("").to_string();
 
-                    log_message(format!("{:?}", data.text_document_uri));
+                    log_message(&client, format!("{:?}", data.text_document_uri));
+                    let encoding = *encoding.lock().unwrap();
                     retrieve_expr_with_pos(
                         &thread,
+                        encoding,
                         &data.text_document_uri,
                         &data.position,
                         |expr, byte_pos| {
                             let type_env = thread.global_env().get_env();
                             let (_, metadata_map) =
                                 gluon::check::metadata::metadata(&*type_env, expr);
-                            log_message(format!("{}  {:?}", item.label, metadata_map));
+                            log_message(&client, format!("{}  {:?}", label, metadata_map));
                             Ok(
                                 completion::suggest_metadata(
                                     &metadata_map,
                                     &*type_env,
                                     expr,
                                     byte_pos,
-                                    &item.label,
+                                    &label,
                                 ).and_then(|metadata| metadata.comment.clone()),
                             )
                         },
                     ).map(|comment| {
-                        log_message(format!("{:?}", comment));
-                        item.documentation = comment;
+                        log_message(&client, format!("{:?}", comment));
+                        if let Some(comment) = comment {
+                            item["documentation"] = Value::from(comment);
+                        }
                         item
                     })
                         .into_future()
@@ -677,43 +3283,91 @@ pub fn run() {
 
             io.add_async_method(
                 "textDocument/hover",
-                ServerCommand::new(HoverCommand(thread.clone())),
+                ServerCommand::new(HoverCommand {
+                    thread: thread.clone(),
+                    encoding: encoding.clone(),
+                }),
+            );
+            io.add_async_method(
+                "textDocument/signatureHelp",
+                ServerCommand::new(SignatureHelpCommand {
+                    thread: thread.clone(),
+                    encoding: encoding.clone(),
+                }),
+            );
+            io.add_async_method(
+                "textDocument/definition",
+                ServerCommand::new(DefinitionCommand {
+                    thread: thread.clone(),
+                    encoding: encoding.clone(),
+                }),
+            );
+            io.add_async_method(
+                "textDocument/references",
+                ServerCommand::new(ReferencesCommand {
+                    thread: thread.clone(),
+                    encoding: encoding.clone(),
+                }),
+            );
+            io.add_async_method(
+                "textDocument/documentHighlight",
+                ServerCommand::new(DocumentHighlightCommand {
+                    thread: thread.clone(),
+                    encoding: encoding.clone(),
+                }),
+            );
+            io.add_async_method(
+                "textDocument/prepareRename",
+                ServerCommand::new(PrepareRenameCommand {
+                    thread: thread.clone(),
+                    encoding: encoding.clone(),
+                }),
+            );
+            io.add_async_method(
+                "textDocument/rename",
+                ServerCommand::new(RenameCommand {
+                    thread: thread.clone(),
+                    encoding: encoding.clone(),
+                }),
+            );
+            io.add_async_method(
+                "textDocument/documentSymbol",
+                ServerCommand::new(DocumentSymbolCommand {
+                    thread: thread.clone(),
+                    encoding: encoding.clone(),
+                }),
+            );
+            io.add_async_method(
+                "workspace/symbol",
+                ServerCommand::new(WorkspaceSymbolCommand {
+                    thread: thread.clone(),
+                    encoding: encoding.clone(),
+                }),
             );
 
-            {
-                let thread = thread.clone();
-                let format = move |params: DocumentFormattingParams| -> BoxFuture<Vec<TextEdit>, _> {
-                    retrieve_expr(&thread, &params.text_document.uri, |module| {
-                        use gluon::parser::format_expr;
-                        let formatted = format_expr(&module.source_string)?;
-                        Ok(vec![
-                            TextEdit {
-                                range: Range {
-                                    start: location_to_position(&module
-                                        .lines
-                                        .location(0.into())
-                                        .ok_or_else(|| {
-                                            ServerError::from(
-                                                &"Unable to translate index to location",
-                                            )
-                                        })?),
-                                    end: location_to_position(&module
-                                        .lines
-                                        .location(module.source_string.len().into())
-                                        .ok_or_else(|| {
-                                            ServerError::from(
-                                                &"Unable to translate index to location",
-                                            )
-                                        })?),
-                                },
-                                new_text: formatted,
-                            },
-                        ])
-                    }).into_future()
-                        .boxed()
-                };
-                io.add_async_method("textDocument/formatting", ServerCommand::new(format));
-            }
+            io.add_async_method(
+                "textDocument/formatting",
+                ServerCommand::new(FormattingCommand {
+                    thread: thread.clone(),
+                    documents: documents.clone(),
+                    encoding: encoding.clone(),
+                }),
+            );
+            io.add_async_method(
+                "textDocument/rangeFormatting",
+                ServerCommand::new(RangeFormattingCommand {
+                    thread: thread.clone(),
+                    documents: documents.clone(),
+                    encoding: encoding.clone(),
+                }),
+            );
+            io.add_async_method(
+                "textDocument/onTypeFormatting",
+                ServerCommand::new(OnTypeFormattingCommand {
+                    documents: documents.clone(),
+                    encoding: encoding.clone(),
+                }),
+            );
 
             io.add_async_method("shutdown", |_| futures::finished(Value::from(0)).boxed());
             let exit_token = Arc::new(AtomicBool::new(false));
@@ -725,53 +3379,197 @@ pub fn run() {
             }
             io.add_notification(
                 "textDocument/didOpen",
-                ServerCommand::new(TextDocumentDidOpen(thread.clone())),
+                ServerCommand::new(TextDocumentDidOpen {
+                    client: client.clone(),
+                    thread: thread.clone(),
+                    settings: settings.clone(),
+                    documents: documents.clone(),
+                    work_queue: work_queue.clone(),
+                    encoding: encoding.clone(),
+                }),
             );
             io.add_notification(
                 "textDocument/didChange",
-                ServerCommand::new(TextDocumentDidChange(work_queue.clone())),
+                ServerCommand::new(TextDocumentDidChange {
+                    client: client.clone(),
+                    thread: thread.clone(),
+                    documents: documents.clone(),
+                    work_queue: work_queue.clone(),
+                    encoding: encoding.clone(),
+                }),
+            );
+            io.add_notification(
+                "textDocument/didSave",
+                ServerCommand::new(TextDocumentDidSave {
+                    client: client.clone(),
+                    thread: thread.clone(),
+                    documents: documents.clone(),
+                    work_queue: work_queue.clone(),
+                }),
+            );
+            io.add_notification(
+                "workspace/didChangeConfiguration",
+                ServerCommand::new(WorkspaceDidChangeConfiguration {
+                    client: client.clone(),
+                    thread: thread.clone(),
+                    settings: settings.clone(),
+                    documents: documents.clone(),
+                    work_queue: work_queue.clone(),
+                }),
             );
+            io.add_notification(
+                "workspace/didChangeWatchedFiles",
+                ServerCommand::new(WorkspaceDidChangeWatchedFiles {
+                    thread: thread.clone(),
+                    documents: documents.clone(),
+                    work_queue: work_queue.clone(),
+                }),
+            );
+            io.add_notification(
+                "textDocument/didClose",
+                ServerCommand::new(TextDocumentDidClose {
+                    client: client.clone(),
+                    thread: thread.clone(),
+                    documents: documents.clone(),
+                    work_queue: work_queue.clone(),
+                }),
+            );
+
+            let cancellations = Cancellations::default();
+            let (messages, received) = mpsc::channel();
+            {
+                let client = client.clone();
+                let cancellations = cancellations.clone();
+                // Messages are read on their own thread so that a request can be cancelled while
+                // earlier messages are still being handled
+                thread::Builder::new()
+                    .name("reader".to_string())
+                    .spawn(move || {
+                        let mut input = input;
+                        loop {
+                            let json = match read_message(&mut input) {
+                                Ok(Some(json)) => json,
+                                Ok(None) => break,
+                                Err(err) => {
+                                    log_message!(client, "Unable to read message: {}", err);
+                                    break;
+                                }
+                            };
+                            if cancellations.cancel_message(&json) {
+                                continue;
+                            }
+                            let request = cancellations.register_message(&json);
+                            if messages.send((json, request)).is_err() {
+                                break;
+                            }
+                        }
+                    })
+                    .unwrap();
+            }
+            let io = Arc::new(io);
+
+            // Requests are handled concurrently and respond in whichever order they finish
+            let (requests, received_requests) = mpsc::channel();
+            let received_requests = Arc::new(Mutex::new(received_requests));
+            let workers: Vec<_> = (0..REQUEST_THREADS)
+                .map(|i| {
+                    let io = io.clone();
+                    let cancellations = cancellations.clone();
+                    let received_requests = received_requests.clone();
+                    let client = client.clone();
+                    thread::Builder::new()
+                        .name(format!("request-{}", i))
+                        .spawn(move || loop {
+                            let request = received_requests.lock().unwrap().recv();
+                            let (json, id, token, snapshot): (String, _, _, _) = match request {
+                                Ok(request) => request,
+                                Err(_) => break,
+                            };
+                            // A panicking handler must neither take the worker down with it nor
+                            // leave the client waiting for a response
+                            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                                with_document_snapshot(snapshot, || {
+                                    with_cancellation_token(token, || io.handle_request_sync(&json))
+                                })
+                            }));
+                            cancellations.remove(&id);
+                            let response = match result {
+                                Ok(response) => response,
+                                Err(err) => {
+                                    let message = panic_message(&*err);
+                                    log_message!(client, "Panic in request: `{}`", message);
+                                    Some(internal_error_response(id, message))
+                                }
+                            };
+                            if let Some(response) = response {
+                                client.send_message_str(response);
+                            }
+                        })
+                        .unwrap()
+                })
+                .collect();
 
-            let mut input = BufReader::new(io::stdin());
-            let mut output = io::stdout();
-
-            (|| -> Result<(), Box<StdError>> {
-                while !exit_token.load(atomic::Ordering::SeqCst) {
-                    match try!(read_message(&mut input)) {
-                        Some(json) => {
-                            debug!("Handle: {}", json);
-                            if let Some(response) = io.handle_request_sync(&json) {
-                                try!(write_message_str(&mut output, &response));
-                                try!(output.flush());
+            while !exit_token.load(atomic::Ordering::SeqCst) {
+                let (json, request) = match received.recv() {
+                    Ok(message) => message,
+                    Err(_) => break,
+                };
+                debug!("Handle: {}", json);
+                match request {
+                    Some((id, token)) => {
+                        // The text is taken before any later notification can change it
+                        let snapshot = document_snapshot(&documents, &json);
+                        requests.send((json, id, token, snapshot)).unwrap()
+                    }
+                    // Notifications change the state which later messages observe so they are
+                    // handled in the order they were received
+                    None => {
+                        let result =
+                            panic::catch_unwind(AssertUnwindSafe(|| io.handle_request_sync(&json)));
+                        match result {
+                            Ok(Some(response)) => client.send_message_str(response),
+                            Ok(None) => (),
+                            Err(err) => {
+                                let message = panic_message(&*err);
+                                log_message!(client, "Panic in notification: `{}`", message);
                             }
                         }
-                        None => return Ok(()),
                     }
                 }
-                Ok(())
-            })().unwrap();
+            }
+
+            // Let the requests which were already received finish before exiting
+            drop(requests);
+            for worker in workers {
+                let _ = worker.join();
+            }
         })
     };
 
     // Spawn a separate thread which runs a returns diagnostic information
-    thread::Builder::new()
-        .name("diagnostics".to_string())
-        .spawn(move || {
-            let diagnostics = DiagnosticProcessor {
-                thread: thread,
-                work_queue: work_queue,
-            };
-            diagnostics.run();
-        })
-        .unwrap();
+    let diagnostics = {
+        let work_queue = work_queue.clone();
+        thread::Builder::new()
+            .name("diagnostics".to_string())
+            .spawn(move || {
+                let diagnostics = DiagnosticProcessor {
+                    client: client,
+                    thread: thread,
+                    settings: settings,
+                    work_queue: work_queue,
+                    encoding: encoding,
+                };
+                diagnostics.run();
+            })
+            .unwrap()
+    };
 
     if let Err(err) = handle.join() {
-        let msg = err.downcast_ref::<&'static str>()
-            .cloned()
-            .or_else(|| err.downcast_ref::<String>().map(|s| &s[..]))
-            .unwrap_or("Any");
-        log_message!("Panic: `{}`", msg);
+        log_message!(writer.sender(), "Panic: `{}`", panic_message(&*err));
     }
+    work_queue.close();
+    let _ = diagnostics.join();
+    writer.close();
 }
 
 #[cfg(test)]
@@ -781,7 +3579,21 @@ mod tests {
 
     use url::Url;
 
-    use super::strip_file_prefix;
+    use gluon::base::pos::BytePos;
+    use gluon::base::source;
+
+    use futures::{self, BoxFuture, Future};
+
+    use jsonrpc_core::{ErrorCode, Params, RpcMethodSimple, Value};
+
+    use languageserver_types::{FormattingOptions, Position, Range, TextDocumentContentChangeEvent};
+
+    use rpc::{CancellationToken, ServerCommand, ServerError, REQUEST_CANCELLED,
+              with_cancellation_token};
+
+    use super::{apply_change, byte_pos_to_position, format_source, fuzzy_match, import_path_start,
+                importers, newline_indent, position_to_byte_pos, strip_file_prefix,
+                PositionEncoding};
 
     #[test]
     fn test_strip_file_prefix() {
@@ -791,4 +3603,227 @@ mod tests {
         ).unwrap();
         assert_eq!(renamed, "test");
     }
+
+    #[test]
+    fn apply_incremental_change() {
+        let mut source = String::from("let x = 1\nlet y = x\ny\n");
+        apply_change(
+            PositionEncoding::Utf16,
+            &mut source,
+            TextDocumentContentChangeEvent {
+                range: Some(Range {
+                    start: Position {
+                        line: 1,
+                        character: 4,
+                    },
+                    end: Position {
+                        line: 1,
+                        character: 5,
+                    },
+                }),
+                range_length: Some(1),
+                text: "z".into(),
+            },
+        ).unwrap_or_else(|err| panic!("{}", err.message));
+        apply_change(
+            PositionEncoding::Utf16,
+            &mut source,
+            TextDocumentContentChangeEvent {
+                range: Some(Range {
+                    start: Position {
+                        line: 2,
+                        character: 0,
+                    },
+                    end: Position {
+                        line: 2,
+                        character: 1,
+                    },
+                }),
+                range_length: Some(1),
+                text: "z + 2".into(),
+            },
+        ).unwrap_or_else(|err| panic!("{}", err.message));
+        assert_eq!(source, "let x = 1\nlet z = x\nz + 2\n");
+    }
+
+    #[test]
+    fn apply_full_change() {
+        let mut source = String::from("let x = 1\nx\n");
+        apply_change(
+            PositionEncoding::Utf16,
+            &mut source,
+            TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: "2".into(),
+            },
+        ).unwrap_or_else(|err| panic!("{}", err.message));
+        assert_eq!(source, "2");
+    }
+
+    #[test]
+    fn importers_are_ordered_after_their_imports() {
+        let a = [];
+        let b = ["a".to_string(), "c".to_string()];
+        let c = ["a".to_string()];
+        let d = ["b".to_string()];
+        let graph = [("a", &a[..]), ("b", &b[..]), ("c", &c[..]), ("d", &d[..])];
+        assert_eq!(importers(&graph, "a"), ["c", "b", "d"]);
+        assert_eq!(importers(&graph, "c"), ["b", "d"]);
+        assert_eq!(importers(&graph, "d"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn importers_with_cycle() {
+        let a = ["b".to_string()];
+        let b = ["a".to_string()];
+        let graph = [("a", &a[..]), ("b", &b[..])];
+        assert_eq!(importers(&graph, "a"), ["b"]);
+    }
+
+    #[test]
+    fn newline_indent_after_block_start() {
+        let options = FormattingOptions {
+            tab_size: 2,
+            insert_spaces: true,
+            properties: Default::default(),
+        };
+        let indent = |source: &str| {
+            newline_indent(PositionEncoding::Utf16, source, 1, &options).map(|edit| edit.new_text)
+        };
+        assert_eq!(indent("  if x then\n"), Some("    ".to_string()));
+        assert_eq!(indent("else\ny"), Some("  ".to_string()));
+        assert_eq!(indent("match x with\n| y ->\n"), None);
+        assert_eq!(indent("x ==\n"), None);
+        assert_eq!(indent("let x = within\n"), None);
+        assert_eq!(indent("let x =\n  1"), None);
+    }
+
+    #[test]
+    fn newline_indent_ignores_strings_and_comments() {
+        let options = FormattingOptions {
+            tab_size: 2,
+            insert_spaces: true,
+            properties: Default::default(),
+        };
+        let indent = |source: &str, line| {
+            newline_indent(PositionEncoding::Utf16, source, line, &options)
+                .map(|edit| edit.new_text)
+        };
+        let indented = |indent: &str| Some(indent.to_string());
+        assert_eq!(indent("let a =\n  let b = \"in\"\n  in\n    b", 3), indented("  "));
+        assert_eq!(indent("let a =\n  let b = 1 // in\n  in\n    b", 3), indented("  "));
+        assert_eq!(
+            indent("let a =\n  let b = 1\n  /* in\n  */ in\n    b", 4),
+            indented("  ")
+        );
+        assert_eq!(indent("let a =\n  let b = '\"' in\n    b", 2), indented("  "));
+        assert_eq!(indent("let x' = 1 in\n  x'", 1), indented(""));
+        assert_eq!(indent("x // then\ny", 1), None);
+        assert_eq!(indent("\"if x then\"\ny", 1), None);
+    }
+
+    #[test]
+    fn refuse_to_format_parse_errors() {
+        assert_eq!(format_source("let x = 1\nx\n").ok(), Some("let x = 1\nx\n".to_string()));
+        let err = format_source("let x = \nx\n").unwrap_err();
+        assert!(
+            err.message.starts_with("Unable to format a module with parse errors"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn utf16_positions() {
+        let source = "let x = \"😀é\"\r\nx\n";
+        let lines = source::Lines::new(source);
+        let byte_pos = |line, character| {
+            position_to_byte_pos(
+                PositionEncoding::Utf16,
+                &lines,
+                source,
+                &Position {
+                    line: line,
+                    character: character,
+                },
+            ).ok()
+                .map(|pos| pos.to_usize())
+        };
+        assert_eq!(byte_pos(0, 9), Some(9));
+        assert_eq!(byte_pos(0, 11), Some(13));
+        assert_eq!(byte_pos(0, 12), Some(15));
+        assert_eq!(byte_pos(0, 100), Some(16));
+        assert_eq!(byte_pos(1, 1), Some(19));
+        assert_eq!(byte_pos(3, 0), None);
+
+        let position =
+            byte_pos_to_position(PositionEncoding::Utf16, &lines, source, BytePos::from(15)).ok();
+        assert_eq!(
+            position,
+            Some(Position {
+                line: 0,
+                character: 12,
+            })
+        );
+    }
+
+    #[test]
+    fn utf8_positions() {
+        let source = "let x = \"😀é\"\nx\n";
+        let lines = source::Lines::new(source);
+        let position = Position {
+            line: 0,
+            character: 13,
+        };
+        let byte_pos = position_to_byte_pos(PositionEncoding::Utf8, &lines, source, &position)
+            .ok()
+            .map(|pos| pos.to_usize());
+        assert_eq!(byte_pos, Some(13));
+
+        let position =
+            byte_pos_to_position(PositionEncoding::Utf8, &lines, source, BytePos::from(15)).ok();
+        assert_eq!(
+            position,
+            Some(Position {
+                line: 0,
+                character: 15,
+            })
+        );
+    }
+
+    #[test]
+    fn path_in_import() {
+        assert_eq!(import_path_start("let m = import! \"std/"), Some(17));
+        assert_eq!(import_path_start("import!\""), Some(8));
+        assert_eq!(import_path_start("let m = import!( \"std/"), Some(18));
+        assert_eq!(import_path_start("import! (\""), Some(10));
+        assert_eq!(import_path_start("import! \"std/map.glu\" "), None);
+        assert_eq!(import_path_start("let m = x"), None);
+    }
+
+    #[test]
+    fn cancelled_request() {
+        let command = ServerCommand::new(|_: ()| -> BoxFuture<i32, ServerError<()>> {
+            futures::finished(1).boxed()
+        });
+        let token = CancellationToken::default();
+        let result = with_cancellation_token(token.clone(), || command.call(Params::None).wait());
+        assert_eq!(result, Ok(Value::from(1)));
+
+        token.cancel();
+        let result = with_cancellation_token(token, || command.call(Params::None).wait());
+        assert_eq!(
+            result.map_err(|err| err.code),
+            Err(ErrorCode::ServerError(REQUEST_CANCELLED))
+        );
+    }
+
+    #[test]
+    fn fuzzy_match_in_order() {
+        assert!(fuzzy_match("", "anything"));
+        assert!(fuzzy_match("fldl", "fold_left"));
+        assert!(fuzzy_match("Map", "empty_map"));
+        assert!(!fuzzy_match("tf", "fold_left"));
+    }
 }
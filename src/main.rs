@@ -1,6 +1,231 @@
-
+extern crate clap;
+extern crate env_logger;
 extern crate gluon_language_server;
+#[macro_use]
+extern crate log;
+
+use std::io::{self, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process;
+use std::thread;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use gluon_language_server::check::{self, Format};
+use gluon_language_server::format;
 
 fn main() {
-    gluon_language_server::run();
+    env_logger::init().unwrap();
+
+    let matches = App::new("gluon_language-server")
+        .version(env!("CARGO_PKG_VERSION"))
+        .arg(
+            Arg::with_name("port")
+                .long("port")
+                .value_name("PORT")
+                .takes_value(true)
+                .help("Listens for clients on a TCP port instead of using stdio"),
+        )
+        .arg(
+            Arg::with_name("socket")
+                .long("socket")
+                .value_name("PATH")
+                .takes_value(true)
+                .conflicts_with("port")
+                .help("Listens for clients on a Unix domain socket instead of using stdio"),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .long("quiet")
+                .help("Does not print the address which is listened on"),
+        )
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("Typechecks gluon files and prints any errors")
+                .arg(
+                    Arg::with_name("paths")
+                        .value_name("PATH")
+                        .multiple(true)
+                        .help("Files or directories to check, defaults to the current directory"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["human", "json"])
+                        .default_value("human")
+                        .help("How the errors are printed"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("fmt")
+                .about("Formats gluon files in place")
+                .arg(
+                    Arg::with_name("paths")
+                        .value_name("PATH")
+                        .multiple(true)
+                        .help("Files or directories to format, defaults to the current directory"),
+                )
+                .arg(
+                    Arg::with_name("check")
+                        .long("check")
+                        .help("Prints a diff for each file which is not formatted, without \
+                               changing any files"),
+                ),
+        )
+        .get_matches();
+
+    match matches.subcommand() {
+        ("check", Some(matches)) => run_check(matches),
+        ("fmt", Some(matches)) => run_fmt(matches),
+        _ => (),
+    }
+
+    let quiet = matches.is_present("quiet");
+
+    if let Some(port) = matches.value_of("port") {
+        let listener = TcpListener::bind(&format!("127.0.0.1:{}", port)[..]).unwrap();
+        if !quiet {
+            let port = listener.local_addr().unwrap().port();
+            writeln!(io::stderr(), "Listening on port {}", port).unwrap();
+        }
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => serve(stream),
+                Err(err) => error!("Unable to accept a client: {}", err),
+            }
+        }
+    } else if let Some(path) = matches.value_of("socket") {
+        listen_unix(path, quiet);
+    } else {
+        gluon_language_server::start_server(BufReader::new(io::stdin()), io::stdout());
+    }
+}
+
+/// A connection to a client of the server
+trait Connection: Read + Write + Send + Sized + 'static {
+    fn try_clone(&self) -> io::Result<Self>;
+    fn shutdown(&self) -> io::Result<()>;
+}
+
+impl Connection for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+    fn shutdown(&self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+#[cfg(unix)]
+impl Connection for UnixStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+    fn shutdown(&self) -> io::Result<()> {
+        UnixStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+/// Serves the client on `stream` on its own thread so that several editors can use the server at
+/// the same time
+fn serve<S>(stream: S)
+where
+    S: Connection,
+{
+    let (input, output) = match (stream.try_clone(), stream.try_clone()) {
+        (Ok(input), Ok(output)) => (input, output),
+        (Err(err), _) | (_, Err(err)) => {
+            error!("Unable to serve a client: {}", err);
+            return;
+        }
+    };
+    let result = thread::Builder::new()
+        .name("session".to_string())
+        .spawn(move || {
+            gluon_language_server::start_server(BufReader::new(input), output);
+            // The reader thread keeps its own handle open
+            let _ = stream.shutdown();
+        });
+    if let Err(err) = result {
+        error!("Unable to serve a client: {}", err);
+    }
+}
+
+#[cfg(unix)]
+fn listen_unix(path: &str, quiet: bool) {
+    use std::fs;
+    use std::os::unix::net::UnixListener;
+
+    // A socket left behind by a server which did not exit cleanly would make `bind` fail, but a
+    // socket which still accepts connections belongs to a running server
+    match UnixStream::connect(path) {
+        Ok(_) => {
+            writeln!(io::stderr(), "error: A server is already listening on {}", path).unwrap();
+            process::exit(2)
+        }
+        Err(ref err) if err.kind() == io::ErrorKind::ConnectionRefused => {
+            let _ = fs::remove_file(path);
+        }
+        Err(_) => (),
+    }
+    let listener = match UnixListener::bind(path) {
+        Ok(listener) => listener,
+        Err(err) => {
+            writeln!(io::stderr(), "error: Unable to listen on {}: {}", path, err).unwrap();
+            process::exit(2)
+        }
+    };
+    if !quiet {
+        writeln!(io::stderr(), "Listening on {}", path).unwrap();
+    }
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => serve(stream),
+            Err(err) => error!("Unable to accept a client: {}", err),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn listen_unix(_path: &str, _quiet: bool) {
+    panic!("Unix domain sockets are not supported on this platform")
+}
+
+fn paths(matches: &ArgMatches) -> Vec<PathBuf> {
+    match matches.values_of("paths") {
+        Some(paths) => paths.map(PathBuf::from).collect(),
+        None => vec![PathBuf::from(".")],
+    }
+}
+
+fn run_check(matches: &ArgMatches) -> ! {
+    let format = match matches.value_of("format") {
+        Some("json") => Format::Json,
+        _ => Format::Human,
+    };
+    let stdout = io::stdout();
+    match check::check(stdout.lock(), &paths(matches), format) {
+        Ok(false) => process::exit(0),
+        Ok(true) => process::exit(1),
+        Err(err) => {
+            writeln!(io::stderr(), "error: {}", err).unwrap();
+            process::exit(2)
+        }
+    }
+}
+
+fn run_fmt(matches: &ArgMatches) -> ! {
+    let stdout = io::stdout();
+    match format::format(stdout.lock(), &paths(matches), matches.is_present("check")) {
+        Ok(false) => process::exit(0),
+        Ok(true) => process::exit(1),
+        Err(err) => {
+            writeln!(io::stderr(), "error: {}", err).unwrap();
+            process::exit(2)
+        }
+    }
 }
@@ -0,0 +1,1416 @@
+//! Queries over the typechecked expressions of modules which find the identifiers the user wrote
+//! and what they refer to.
+
+use std::mem;
+
+use gluon::base::ast::{walk_expr, walk_pattern, Expr, Pattern, SpannedExpr, SpannedPattern,
+                       TypeBinding, Typed, TypedIdent, Visitor};
+use gluon::base::pos::{BytePos, Span, Spanned, NO_EXPANSION};
+use gluon::base::symbol::Symbol;
+use gluon::base::types::{arg_iter, ArcType, Type, TypeEnv};
+
+use languageserver_types::SymbolKind;
+
+/// What an identifier in the source refers to
+#[derive(Clone, Debug)]
+pub enum Reference {
+    /// A value, type or variant constructor
+    Symbol(Symbol),
+    /// The field `name` of the record bound to `record` (if the record is bound to a name)
+    Field {
+        record: Option<Symbol>,
+        name: String,
+    },
+    /// The field `name` of the record type `typ` which is declared in the same module. Fields of
+    /// types declared in other modules are only known through the records they are bound to.
+    TypeField { typ: Symbol, name: String },
+}
+
+impl Reference {
+    pub fn refers_to(&self, other: &Reference) -> bool {
+        match (self, other) {
+            (&Reference::Symbol(ref l), &Reference::Symbol(ref r)) => l == r,
+            (
+                &Reference::Field {
+                    record: Some(ref l_record),
+                    name: ref l_name,
+                },
+                &Reference::Field {
+                    record: Some(ref r_record),
+                    name: ref r_name,
+                },
+            ) => l_record == r_record && l_name == r_name,
+            (
+                &Reference::TypeField {
+                    typ: ref l_typ,
+                    name: ref l_name,
+                },
+                &Reference::TypeField {
+                    typ: ref r_typ,
+                    name: ref r_name,
+                },
+            ) => l_typ == r_typ && l_name == r_name,
+            _ => false,
+        }
+    }
+}
+
+/// An identifier written in the source
+#[derive(Clone, Debug)]
+pub struct Occurrence {
+    pub span: Span<BytePos>,
+    pub reference: Reference,
+    /// `true` if the identifier binds `reference` instead of using it
+    pub definition: bool,
+}
+
+/// Where the definition of an identifier can be found
+#[derive(Clone, Debug, PartialEq)]
+pub enum Definition {
+    /// The definition is in the module which was queried
+    Local(Span<BytePos>),
+    /// The identifier refers to the module itself
+    Module(String),
+    /// The definition is the field `field` of the record exported by `module`
+    ModuleField { module: String, field: String },
+}
+
+/// What an occurrence refers to, identified so that occurrences in different modules can be
+/// compared
+#[derive(Clone, Debug, PartialEq)]
+pub enum Target {
+    /// A binding which is only visible inside `module`
+    Local {
+        module: String,
+        span: Span<BytePos>,
+    },
+    /// The module `name`
+    Module(String),
+    /// The field `field` of the record exported by `module`
+    ModuleField { module: String, field: String },
+}
+
+/// Returns every identifier in `expr` which were written in `source`
+pub fn occurrences(expr: &SpannedExpr<Symbol>, source: &str) -> Vec<Occurrence> {
+    let mut visitor = OccurrenceVisitor {
+        source: source,
+        occurrences: Vec::new(),
+        types: Vec::new(),
+        record_types: Vec::new(),
+    };
+    visitor.visit_expr(expr);
+    visitor.occurrences
+}
+
+/// Returns the innermost occurrence at `pos`
+pub fn occurrence_at(occurrences: &[Occurrence], pos: BytePos) -> Option<&Occurrence> {
+    occurrences
+        .iter()
+        .filter(|occurrence| {
+            occurrence.span.start <= pos && pos <= occurrence.span.end
+        })
+        .min_by_key(|occurrence| {
+            // Prefer uses since they can be followed further than the binding they share a span
+            // with (`{ x }` in a pattern both binds `x` and uses the field `x`)
+            (
+                occurrence.span.end - occurrence.span.start,
+                occurrence.definition,
+            )
+        })
+}
+
+/// Finds the definition of the identifier at `pos`. `is_module` decides which global names refer
+/// to loaded modules.
+pub fn definition<F>(
+    expr: &SpannedExpr<Symbol>,
+    source: &str,
+    pos: BytePos,
+    is_module: F,
+) -> Option<Definition>
+where
+    F: Fn(&str) -> bool,
+{
+    let occurrences = occurrences(expr, source);
+    let occurrence = match occurrence_at(&occurrences, pos) {
+        Some(occurrence) => occurrence,
+        None => return None,
+    };
+    resolve(expr, &occurrences, &occurrence.reference, &is_module)
+}
+
+/// Returns what `occurrence`, found in the module named `module`, refers to
+pub fn target<F>(
+    module: &str,
+    expr: &SpannedExpr<Symbol>,
+    occurrences: &[Occurrence],
+    occurrence: &Occurrence,
+    is_module: &F,
+) -> Option<Target>
+where
+    F: Fn(&str) -> bool,
+{
+    let definition = match occurrence.reference {
+        Reference::Field { record: None, .. } if occurrence.definition => {
+            Definition::Local(occurrence.span)
+        }
+        ref reference => match resolve(expr, occurrences, reference, is_module) {
+            Some(definition) => definition,
+            None => return None,
+        },
+    };
+    Some(match definition {
+        Definition::Local(span) => match exported_binding(expr, occurrences, span) {
+            Some(field) => Target::ModuleField {
+                module: module.to_string(),
+                field: field,
+            },
+            None => Target::Local {
+                module: module.to_string(),
+                span: span,
+            },
+        },
+        Definition::Module(name) => Target::Module(name),
+        Definition::ModuleField { module, field } => Target::ModuleField {
+            module: module,
+            field: field,
+        },
+    })
+}
+
+/// Returns `true` if `occurrences` refer to the module `module`
+pub fn imports(occurrences: &[Occurrence], module: &str) -> bool {
+    occurrences.iter().any(|occurrence| match occurrence.reference {
+        Reference::Symbol(ref symbol) => symbol.as_ref() == module,
+        Reference::Field { .. } | Reference::TypeField { .. } => false,
+    })
+}
+
+/// Returns the modules which are referred to by `occurrences`, sorted by name
+pub fn imported_modules<F>(occurrences: &[Occurrence], is_module: F) -> Vec<String>
+where
+    F: Fn(&str) -> bool,
+{
+    let mut modules: Vec<_> = occurrences
+        .iter()
+        .filter_map(|occurrence| match occurrence.reference {
+            Reference::Symbol(ref symbol) if is_module(symbol.as_ref()) => {
+                Some(symbol.as_ref().to_string())
+            }
+            _ => None,
+        })
+        .collect();
+    modules.sort();
+    modules.dedup();
+    modules
+}
+
+fn resolve<F>(
+    expr: &SpannedExpr<Symbol>,
+    occurrences: &[Occurrence],
+    reference: &Reference,
+    is_module: &F,
+) -> Option<Definition>
+where
+    F: Fn(&str) -> bool,
+{
+    match *reference {
+        Reference::Symbol(ref symbol) => {
+            if is_module(symbol.as_ref()) {
+                return Some(Definition::Module(symbol.as_ref().to_string()));
+            }
+            let binding = match occurrences.iter().find(|occurrence| {
+                occurrence.definition && occurrence.reference.refers_to(reference)
+            }) {
+                Some(binding) => binding,
+                None => return None,
+            };
+            // A binding destructured out of an imported module is defined by the module's field
+            let field = occurrences.iter().find(|occurrence| {
+                !occurrence.definition && occurrence.span == binding.span &&
+                    match occurrence.reference {
+                        Reference::Field { .. } | Reference::TypeField { .. } => true,
+                        Reference::Symbol(_) => false,
+                    }
+            });
+            match field.and_then(|field| resolve(expr, occurrences, &field.reference, is_module)) {
+                Some(definition @ Definition::ModuleField { .. }) => Some(definition),
+                _ => Some(Definition::Local(binding.span)),
+            }
+        }
+        Reference::Field {
+            record: Some(ref record),
+            ref name,
+        } => {
+            match bound_module(expr, record, is_module) {
+                Some(module) => Some(Definition::ModuleField {
+                    module: module,
+                    field: name.clone(),
+                }),
+                None => {
+                    with_binding(expr, record, |bound| record_field(bound, name))
+                        .and_then(|span| span)
+                        .map(Definition::Local)
+                }
+            }
+        }
+        Reference::Field { record: None, .. } => None,
+        Reference::TypeField { .. } => occurrences
+            .iter()
+            .find(|occurrence| {
+                occurrence.definition && occurrence.reference.refers_to(reference)
+            })
+            .map(|field| Definition::Local(field.span)),
+    }
+}
+
+/// Returns the edits which renames each occurrence for which `is_target` is `true` to `new_name`
+pub fn rename_edits(
+    source: &str,
+    occurrences: &[Occurrence],
+    is_target: &[bool],
+    new_name: &str,
+) -> Vec<(Span<BytePos>, String)> {
+    let mut spans: Vec<_> = occurrences
+        .iter()
+        .zip(is_target)
+        .filter(|&(_, &is_target)| is_target)
+        .map(|(occurrence, _)| occurrence.span)
+        .collect();
+    spans.sort_by_key(|span| span.start);
+    spans.dedup();
+
+    spans
+        .into_iter()
+        .map(|span| {
+            let old_name = &source[span.start.to_usize()..span.end.to_usize()];
+            let (mut field, mut binding) = (None, None);
+            for (occurrence, &is_target) in occurrences.iter().zip(is_target) {
+                if occurrence.span == span {
+                    // A field may be referred to through both its record and its record type
+                    match occurrence.reference {
+                        Reference::Field { .. } | Reference::TypeField { .. } => {
+                            field = Some(field.unwrap_or(false) || is_target)
+                        }
+                        Reference::Symbol(_) => binding = Some(is_target),
+                    }
+                }
+            }
+            // `{ x }` is both a field and a binding. If only one of them is renamed the
+            // shorthand needs to be expanded
+            let new_text = match (field, binding) {
+                (Some(true), Some(false)) => format!("{} = {}", new_name, old_name),
+                (Some(false), Some(true)) => format!("{} = {}", old_name, new_name),
+                _ => new_name.to_string(),
+            };
+            (span, new_text)
+        })
+        .collect()
+}
+
+/// Returns the span of an identifier which would refer to another binding if the bindings
+/// `symbols` were renamed to `new_name`. Either a use of a renamed binding is in the scope of
+/// another binding named `new_name` or a use of another `new_name` is in the scope of a renamed
+/// binding.
+pub fn rename_conflict(
+    expr: &SpannedExpr<Symbol>,
+    symbols: &[Symbol],
+    new_name: &str,
+) -> Option<Span<BytePos>> {
+    let mut visitor = ScopeVisitor {
+        symbols: symbols,
+        new_name: new_name,
+        scope: Vec::new(),
+        conflict: None,
+    };
+    visitor.visit_expr(expr);
+    visitor.conflict
+}
+
+/// Adds the symbols bound by `pattern` to `symbols`
+fn pattern_symbols(pattern: &SpannedPattern<Symbol>, symbols: &mut Vec<Symbol>) {
+    match pattern.value {
+        Pattern::Ident(ref id) => symbols.push(id.name.clone()),
+        Pattern::Record {
+            ref types,
+            ref fields,
+            ..
+        } => {
+            for field in types {
+                symbols.push(field.value.clone().unwrap_or_else(|| field.name.value.clone()));
+            }
+            for field in fields {
+                match field.value {
+                    Some(ref value) => pattern_symbols(value, symbols),
+                    None => symbols.push(field.name.value.clone()),
+                }
+            }
+        }
+        Pattern::Tuple { ref elems, .. } => for elem in elems {
+            pattern_symbols(elem, symbols);
+        },
+        Pattern::Constructor(_, ref args) => for arg in args {
+            pattern_symbols(arg, symbols);
+        },
+        Pattern::Error => (),
+    }
+}
+
+struct ScopeVisitor<'a> {
+    symbols: &'a [Symbol],
+    new_name: &'a str,
+    /// The bindings in scope, innermost last
+    scope: Vec<Symbol>,
+    conflict: Option<Span<BytePos>>,
+}
+
+impl<'a> ScopeVisitor<'a> {
+    fn use_symbol(&mut self, symbol: &Symbol, span: Span<BytePos>) {
+        let renamed = self.symbols.contains(symbol);
+        if self.conflict.is_some() || (!renamed && symbol.declared_name() != self.new_name) {
+            return;
+        }
+        for bound in self.scope.iter().rev() {
+            if bound == symbol {
+                return;
+            }
+            let shadows = if renamed {
+                bound.declared_name() == self.new_name && !self.symbols.contains(bound)
+            } else {
+                self.symbols.contains(bound)
+            };
+            if shadows {
+                self.conflict = Some(span);
+                return;
+            }
+        }
+    }
+}
+
+impl<'a> Visitor for ScopeVisitor<'a> {
+    type Ident = Symbol;
+
+    fn visit_expr(&mut self, expr: &SpannedExpr<Symbol>) {
+        let scope_len = self.scope.len();
+        match expr.value {
+            Expr::Ident(ref id) => self.use_symbol(&id.name, expr.span),
+            Expr::Infix(ref lhs, ref op, ref rhs) => {
+                self.visit_expr(lhs);
+                self.use_symbol(&op.value.name, op.span);
+                self.visit_expr(rhs);
+            }
+            Expr::Lambda(ref lambda) => {
+                self.scope.extend(lambda.args.iter().map(|arg| arg.name.clone()));
+                self.visit_expr(&lambda.body);
+            }
+            Expr::LetBindings(ref bindings, ref body) => {
+                // Functions may refer to themselves and the functions declared along with them
+                let recursive = bindings.iter().any(|bind| !bind.args.is_empty());
+                if recursive {
+                    for bind in bindings {
+                        pattern_symbols(&bind.name, &mut self.scope);
+                    }
+                }
+                for bind in bindings {
+                    let bind_len = self.scope.len();
+                    self.scope.extend(bind.args.iter().map(|arg| arg.name.clone()));
+                    self.visit_expr(&bind.expr);
+                    self.scope.truncate(bind_len);
+                    if !recursive {
+                        pattern_symbols(&bind.name, &mut self.scope);
+                    }
+                }
+                self.visit_expr(body);
+            }
+            Expr::TypeBindings(ref bindings, ref body) => {
+                for bind in bindings {
+                    self.scope.push(bind.name.value.clone());
+                    if let Type::Variant(ref row) = **bind.alias.value.unresolved_type() {
+                        self.scope
+                            .extend(row.row_iter().map(|field| field.name.clone()));
+                    }
+                }
+                self.visit_expr(body);
+            }
+            Expr::Match(ref scrutinee, ref alts) => {
+                self.visit_expr(scrutinee);
+                for alt in alts {
+                    pattern_symbols(&alt.pattern, &mut self.scope);
+                    self.visit_expr(&alt.expr);
+                    self.scope.truncate(scope_len);
+                }
+            }
+            _ => walk_expr(self, expr),
+        }
+        self.scope.truncate(scope_len);
+    }
+}
+
+/// Returns the name of the module which `symbol` is bound to
+fn bound_module<F>(expr: &SpannedExpr<Symbol>, symbol: &Symbol, is_module: &F) -> Option<String>
+where
+    F: Fn(&str) -> bool,
+{
+    if is_module(symbol.as_ref()) {
+        return Some(symbol.as_ref().to_string());
+    }
+    let bound = with_binding(expr, symbol, |bound| match bound.value {
+        Expr::Ident(ref id) => Some(id.name.clone()),
+        _ => None,
+    });
+    match bound {
+        Some(Some(ref id)) if id != symbol => bound_module(expr, id, is_module),
+        _ => None,
+    }
+}
+
+/// Calls `f` with the expression which `symbol` is bound to by a `let`
+fn with_binding<F, R>(expr: &SpannedExpr<Symbol>, symbol: &Symbol, f: F) -> Option<R>
+where
+    F: FnOnce(&SpannedExpr<Symbol>) -> R,
+{
+    struct FindBinding<'a, F, R> {
+        symbol: &'a Symbol,
+        f: Option<F>,
+        result: Option<R>,
+    }
+
+    impl<'a, F, R> Visitor for FindBinding<'a, F, R>
+    where
+        F: FnOnce(&SpannedExpr<Symbol>) -> R,
+    {
+        type Ident = Symbol;
+
+        fn visit_expr(&mut self, expr: &SpannedExpr<Symbol>) {
+            if self.result.is_some() {
+                return;
+            }
+            if let Expr::LetBindings(ref bindings, _) = expr.value {
+                for bind in bindings {
+                    match bind.name.value {
+                        Pattern::Ident(ref id) if id.name == *self.symbol => {
+                            let f = self.f.take().expect("binding found only once");
+                            self.result = Some(f(&bind.expr));
+                            return;
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            walk_expr(self, expr);
+        }
+    }
+
+    let mut visitor = FindBinding {
+        symbol: symbol,
+        f: Some(f),
+        result: None,
+    };
+    visitor.visit_expr(expr);
+    visitor.result
+}
+
+/// Returns the names of the fields and types of `expr` if it is a record expression
+fn record_fields(expr: &SpannedExpr<Symbol>) -> Vec<&Spanned<Symbol, BytePos>> {
+    match expr.value {
+        Expr::Record {
+            ref types,
+            ref exprs,
+            ..
+        } => {
+            types
+                .iter()
+                .map(|field| &field.name)
+                .chain(exprs.iter().map(|field| &field.name))
+                .collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Returns the span of the field `name` if `expr` is a record expression
+fn record_field(expr: &SpannedExpr<Symbol>, name: &str) -> Option<Span<BytePos>> {
+    record_fields(expr)
+        .into_iter()
+        .find(|field| field.value.declared_name() == name)
+        .map(|field| field.span)
+}
+
+/// Returns the expression which a module evaluates to
+fn exported_expr(expr: &SpannedExpr<Symbol>) -> &SpannedExpr<Symbol> {
+    let mut expr = expr;
+    loop {
+        match expr.value {
+            Expr::LetBindings(_, ref body) |
+            Expr::TypeBindings(_, ref body) => expr = body,
+            _ => return expr,
+        }
+    }
+}
+
+/// Returns the spans of the bindings at the top level of a module followed by the span of the
+/// expression which the module evaluates to
+pub fn top_level_spans(expr: &SpannedExpr<Symbol>) -> Vec<Span<BytePos>> {
+    let mut spans = Vec::new();
+    let mut expr = expr;
+    loop {
+        match expr.value {
+            Expr::LetBindings(_, ref body) |
+            Expr::TypeBindings(_, ref body) if expr.span.expansion_id != NO_EXPANSION => {
+                expr = body
+            }
+            Expr::LetBindings(ref bindings, ref body) => {
+                spans.extend(bindings.iter().map(|bind| bind.span()));
+                expr = body;
+            }
+            Expr::TypeBindings(ref bindings, ref body) => {
+                spans.extend(bindings.iter().map(|bind| bind.span()));
+                expr = body;
+            }
+            _ => {
+                spans.push(expr.span);
+                break;
+            }
+        }
+    }
+    spans.retain(|span| span.expansion_id == NO_EXPANSION);
+    spans
+}
+
+/// Returns the span of the field `name` in the record which a module evaluates to
+pub fn exported_field(expr: &SpannedExpr<Symbol>, name: &str) -> Option<Span<BytePos>> {
+    record_field(exported_expr(expr), name)
+}
+
+/// Returns the name of the field which exports the binding defined at `span` from a module
+fn exported_binding(
+    expr: &SpannedExpr<Symbol>,
+    occurrences: &[Occurrence],
+    span: Span<BytePos>,
+) -> Option<String> {
+    // `{ x }` exports `x` under its own name so the field and binding are the same
+    let is_exported_by = |field: &Spanned<Symbol, BytePos>| {
+        field.span == span ||
+            occurrences.iter().any(|occurrence| {
+                !occurrence.definition && occurrence.span == field.span &&
+                    match occurrence.reference {
+                        Reference::Symbol(_) => occurrences.iter().any(|binding| {
+                            binding.definition && binding.span == span &&
+                                binding.reference.refers_to(&occurrence.reference)
+                        }),
+                        Reference::Field { .. } | Reference::TypeField { .. } => false,
+                    }
+            })
+    };
+    record_fields(exported_expr(expr))
+        .into_iter()
+        .find(|field| is_exported_by(field))
+        .map(|field| field.value.declared_name().to_string())
+}
+
+/// Returns the span of the function in the innermost function application which `pos` is in
+/// along with the index of the argument at `pos`. An identifier followed by whitespace or an
+/// opening parenthesis is a function which is about to be applied to its first argument.
+pub fn application_at(
+    expr: &SpannedExpr<Symbol>,
+    source: &str,
+    pos: BytePos,
+) -> Option<(Span<BytePos>, usize)> {
+    struct FindApplication<'a> {
+        source: &'a str,
+        pos: BytePos,
+        /// `true` if the expression being visited is the function or an argument of an
+        /// application
+        applied: bool,
+        result: Option<(Span<BytePos>, usize)>,
+    }
+
+    impl<'a> FindApplication<'a> {
+        /// Returns `true` if `pos` is after `start` and in `span`, or after it with only spaces or
+        /// an opening parenthesis in between since the application is still being written there
+        fn in_application(&self, start: BytePos, span: Span<BytePos>) -> bool {
+            let trailing = self.source.get(span.end.to_usize()..).map_or(0, |rest| {
+                rest.len() - rest.trim_left_matches(|c| c == ' ' || c == '\t' || c == '(').len()
+            });
+            span.expansion_id == NO_EXPANSION && start < self.pos &&
+                self.pos <= span.end + BytePos::from(trailing)
+        }
+    }
+
+    impl<'a> Visitor for FindApplication<'a> {
+        type Ident = Symbol;
+
+        fn visit_expr(&mut self, expr: &SpannedExpr<Symbol>) {
+            let applied = mem::replace(&mut self.applied, false);
+            match expr.value {
+                Expr::App(ref func, ref args) => {
+                    if self.in_application(func.span.end, expr.span) {
+                        let active = args.iter().filter(|arg| arg.span.end < self.pos).count();
+                        self.result = Some((func.span, active));
+                    }
+                    for expr in Some(&**func).into_iter().chain(args) {
+                        self.applied = true;
+                        self.visit_expr(expr);
+                    }
+                    return;
+                }
+                Expr::Ident(_) if !applied && self.in_application(expr.span.end, expr.span) => {
+                    self.result = Some((expr.span, 0));
+                }
+                _ => (),
+            }
+            walk_expr(self, expr);
+        }
+    }
+
+    let mut visitor = FindApplication {
+        source: source,
+        pos: pos,
+        applied: false,
+        result: None,
+    };
+    visitor.visit_expr(expr);
+    visitor.result
+}
+
+/// Returns the type of the record and the (partial) field name of the projection at `pos`, if
+/// `pos` is in the field name of a projection
+pub fn projection_at<T>(
+    env: &T,
+    expr: &SpannedExpr<Symbol>,
+    pos: BytePos,
+) -> Option<(ArcType, Symbol)>
+where
+    T: TypeEnv,
+{
+    struct FindProjection<'a, T: 'a> {
+        env: &'a T,
+        pos: BytePos,
+        result: Option<(ArcType, Symbol)>,
+    }
+
+    impl<'a, T> Visitor for FindProjection<'a, T>
+    where
+        T: TypeEnv,
+    {
+        type Ident = Symbol;
+
+        fn visit_expr(&mut self, expr: &SpannedExpr<Symbol>) {
+            if let Expr::Projection(ref record, ref field, _) = expr.value {
+                if expr.span.expansion_id == NO_EXPANSION && record.span.end < self.pos &&
+                    self.pos <= expr.span.end
+                {
+                    self.result = Some((record.env_type_of(self.env), field.clone()));
+                }
+            }
+            walk_expr(self, expr);
+        }
+    }
+
+    let mut visitor = FindProjection {
+        env: env,
+        pos: pos,
+        result: None,
+    };
+    visitor.visit_expr(expr);
+    visitor.result
+}
+
+/// A record construction or record pattern with a field name at the queried position
+#[derive(Clone, Debug)]
+pub struct RecordAt {
+    /// The type the record is expected to have
+    pub typ: ArcType,
+    /// The part of the field name before the queried position
+    pub prefix: String,
+    /// The fields which are already written, except the one being written
+    pub used: Vec<String>,
+}
+
+/// Returns the record construction or record pattern which `pos` is in if `pos` is where the name
+/// of a field is written. Records which are bound by an annotated `let` or passed to a function
+/// are expected to have the annotated or argument type, other records have the type they were
+/// checked to have.
+pub fn record_at<T>(env: &T, expr: &SpannedExpr<Symbol>, pos: BytePos) -> Option<RecordAt>
+where
+    T: TypeEnv,
+{
+    struct FindRecord<'a, T: 'a> {
+        env: &'a T,
+        pos: BytePos,
+        expected: Vec<(Span<BytePos>, ArcType)>,
+        result: Option<RecordAt>,
+    }
+
+    impl<'a, T> FindRecord<'a, T> {
+        fn record<'f, I>(&mut self, span: Span<BytePos>, typ: &ArcType, fields: I)
+        where
+            I: IntoIterator<Item = (&'f Spanned<Symbol, BytePos>, Option<Span<BytePos>>)>,
+        {
+            if span.expansion_id != NO_EXPANSION || self.pos <= span.start ||
+                span.end <= self.pos
+            {
+                return;
+            }
+            let mut prefix = String::new();
+            let mut used = Vec::new();
+            for (name, value) in fields {
+                // Fields written as `{ x }` get a value which does not come after the name
+                if let Some(value) = value {
+                    if name.span.end < value.start && value.start <= self.pos &&
+                        self.pos <= value.end
+                    {
+                        return;
+                    }
+                }
+                let declared_name = name.value.declared_name();
+                if name.span.start <= self.pos && self.pos <= name.span.end {
+                    let len = (self.pos - name.span.start).to_usize();
+                    prefix = declared_name.get(..len).unwrap_or(declared_name).to_string();
+                } else {
+                    used.push(declared_name.to_string());
+                }
+            }
+            let typ = self.expected
+                .iter()
+                .find(|&&(expected_span, _)| expected_span == span)
+                .map_or_else(|| typ.clone(), |&(_, ref expected)| expected.clone());
+            self.result = Some(RecordAt {
+                typ: typ,
+                prefix: prefix,
+                used: used,
+            });
+        }
+    }
+
+    impl<'a, T> Visitor for FindRecord<'a, T>
+    where
+        T: TypeEnv,
+    {
+        type Ident = Symbol;
+
+        fn visit_expr(&mut self, expr: &SpannedExpr<Symbol>) {
+            match expr.value {
+                Expr::LetBindings(ref bindings, _) => {
+                    for bind in bindings.iter().filter(|bind| bind.args.is_empty()) {
+                        self.expected.push((bind.expr.span, bind.typ.clone()));
+                    }
+                }
+                Expr::App(ref func, ref args) => {
+                    let func_type = func.env_type_of(self.env);
+                    for (arg, arg_type) in args.iter().zip(arg_iter(&func_type)) {
+                        self.expected.push((arg.span, arg_type.clone()));
+                    }
+                }
+                Expr::Record {
+                    ref typ,
+                    ref types,
+                    ref exprs,
+                } => {
+                    let fields = types
+                        .iter()
+                        .map(|field| (&field.name, None))
+                        .chain(exprs.iter().map(|field| {
+                            (&field.name, field.value.as_ref().map(|value| value.span))
+                        }));
+                    self.record(expr.span, typ, fields);
+                }
+                _ => (),
+            }
+            walk_expr(self, expr);
+        }
+
+        fn visit_pattern(&mut self, pattern: &SpannedPattern<Symbol>) {
+            if let Pattern::Record {
+                ref typ,
+                ref types,
+                ref fields,
+            } = pattern.value
+            {
+                let fields = types
+                    .iter()
+                    .map(|field| (&field.name, None))
+                    .chain(fields.iter().map(|field| {
+                        (&field.name, field.value.as_ref().map(|value| value.span))
+                    }));
+                self.record(pattern.span, typ, fields);
+            }
+            walk_pattern(self, &pattern.value);
+        }
+    }
+
+    let mut visitor = FindRecord {
+        env: env,
+        pos: pos,
+        expected: Vec::new(),
+        result: None,
+    };
+    visitor.visit_expr(expr);
+    visitor.result
+}
+
+/// Returns the names which `let` binds to modules in `expr`
+pub fn module_bindings<F>(expr: &SpannedExpr<Symbol>, is_module: F) -> Vec<String>
+where
+    F: Fn(&str) -> bool,
+{
+    /// Collects the names bound to an identifier, such as the module an `import!` expands to
+    struct IdentBindings(Vec<(Symbol, Symbol)>);
+
+    impl Visitor for IdentBindings {
+        type Ident = Symbol;
+
+        fn visit_expr(&mut self, expr: &SpannedExpr<Symbol>) {
+            if let Expr::LetBindings(ref bindings, _) = expr.value {
+                for bind in bindings {
+                    match (&bind.name.value, &bind.expr.value) {
+                        (&Pattern::Ident(ref name), &Expr::Ident(ref bound)) => {
+                            self.0.push((name.name.clone(), bound.name.clone()))
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            walk_expr(self, expr);
+        }
+    }
+
+    let mut visitor = IdentBindings(Vec::new());
+    visitor.visit_expr(expr);
+    let mut names: Vec<_> = visitor
+        .0
+        .into_iter()
+        .filter(|&(_, ref bound)| bound_module(expr, bound, &is_module).is_some())
+        .map(|(name, _)| name.declared_name().to_string())
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// A name declared at the top level of a module
+#[derive(Clone, Debug)]
+pub struct Declaration {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub span: Span<BytePos>,
+    /// The name of the declaration which this declaration is a part of
+    pub container: Option<String>,
+}
+
+/// Returns the `let` bindings, types, variant constructors and exported fields declared at the top
+/// level of a module
+pub fn declarations(expr: &SpannedExpr<Symbol>, source: &str) -> Vec<Declaration> {
+    fn pattern_bindings(
+        pattern: &SpannedPattern<Symbol>,
+        bindings: &mut Vec<(String, Span<BytePos>)>,
+    ) {
+        match pattern.value {
+            Pattern::Ident(ref id) => {
+                bindings.push((id.name.declared_name().to_string(), pattern.span))
+            }
+            Pattern::Record { ref fields, .. } => for field in fields {
+                match field.value {
+                    Some(ref value) if value.span != pattern.span => {
+                        pattern_bindings(value, bindings)
+                    }
+                    // `{ x }` binds `x` (the pattern is only filled in after renaming)
+                    _ => bindings.push((
+                        field.name.value.declared_name().to_string(),
+                        field.name.span,
+                    )),
+                }
+            },
+            Pattern::Tuple { elems: ref args, .. } |
+            Pattern::Constructor(_, ref args) => for arg in args {
+                pattern_bindings(arg, bindings);
+            },
+            Pattern::Error => (),
+        }
+    }
+
+    let mut declarations = Vec::new();
+    let mut expr = expr;
+    loop {
+        match expr.value {
+            // Bindings which were not written in the source (such as the implicit prelude) do not
+            // have reliable spans
+            Expr::LetBindings(_, ref body) |
+            Expr::TypeBindings(_, ref body) if expr.span.expansion_id != NO_EXPANSION => {
+                expr = body
+            }
+            Expr::LetBindings(ref bindings, ref body) => {
+                for bind in bindings {
+                    let kind = match bind.expr.value {
+                        Expr::Lambda(_) => SymbolKind::Function,
+                        _ if !bind.args.is_empty() => SymbolKind::Function,
+                        _ => SymbolKind::Variable,
+                    };
+                    let mut names = Vec::new();
+                    pattern_bindings(&bind.name, &mut names);
+                    for (name, span) in names {
+                        declarations.push(Declaration {
+                            name: name,
+                            kind: kind,
+                            span: Span {
+                                end: bind.expr.span.end,
+                                ..span
+                            },
+                            container: None,
+                        });
+                    }
+                }
+                expr = body;
+            }
+            Expr::TypeBindings(ref bindings, ref body) => {
+                for bind in bindings {
+                    let constructors = constructors(source, bind);
+                    let name = bind.name.value.declared_name();
+                    declarations.push(Declaration {
+                        name: name.to_string(),
+                        kind: if constructors.is_empty() {
+                            SymbolKind::Class
+                        } else {
+                            SymbolKind::Enum
+                        },
+                        span: Span {
+                            end: bind.alias.span.end,
+                            ..bind.name.span
+                        },
+                        container: None,
+                    });
+                    declarations.extend(constructors.into_iter().map(|(symbol, span)| {
+                        Declaration {
+                            name: symbol.declared_name().to_string(),
+                            kind: SymbolKind::Constructor,
+                            span: span,
+                            container: Some(name.to_string()),
+                        }
+                    }));
+                }
+                expr = body;
+            }
+            Expr::Record { ref exprs, .. } => {
+                declarations.extend(exprs.iter().map(|field| {
+                    Declaration {
+                        name: field.name.value.declared_name().to_string(),
+                        kind: SymbolKind::Field,
+                        span: field.name.span,
+                        container: None,
+                    }
+                }));
+                break;
+            }
+            _ => break,
+        }
+    }
+    declarations.retain(|declaration| declaration.span.expansion_id == NO_EXPANSION);
+    declarations
+}
+
+/// Returns the variant constructors declared by `bind` along with their spans
+fn constructors(source: &str, bind: &TypeBinding<Symbol>) -> Vec<(Symbol, Span<BytePos>)> {
+    let typ = bind.finalized_alias
+        .as_ref()
+        .map(|alias| alias.unresolved_type().clone())
+        .unwrap_or_else(|| bind.alias.value.unresolved_type().clone());
+    let mut constructors = Vec::new();
+    if let Type::Variant(ref row) = *typ {
+        let mut span = Span::new(bind.name.span.end, bind.alias.span.end);
+        for field in row.row_iter() {
+            // Constructors are always preceded by `|`
+            let found = find_ident(source, span, field.name.declared_name(), |before| {
+                before.trim_right().ends_with('|')
+            });
+            if let Some(constructor_span) = found {
+                constructors.push((field.name.clone(), constructor_span));
+                span = Span::new(constructor_span.end, span.end);
+            }
+        }
+    }
+    constructors
+}
+
+/// Returns the fields of the record type declared by `bind` along with their spans
+fn record_type_fields(source: &str, bind: &TypeBinding<Symbol>) -> Vec<(String, Span<BytePos>)> {
+    let mut fields = Vec::new();
+    if let Type::Record(ref row) = **bind.alias.value.unresolved_type() {
+        let mut span = bind.alias.span;
+        for field in row.row_iter() {
+            // Fields are always preceded by `{` or `,`
+            let found = find_ident(source, span, field.name.declared_name(), |before| {
+                let before = before.trim_right();
+                before.ends_with('{') || before.ends_with(',')
+            });
+            if let Some(field_span) = found {
+                fields.push((field.name.declared_name().to_string(), field_span));
+                span = Span::new(field_span.end, span.end);
+            }
+        }
+    }
+    fields
+}
+
+/// Returns the span of the first identifier `name` in `span` for which `accept` returns `true`.
+/// `accept` is passed the source preceding the identifier.
+fn find_ident<F>(source: &str, span: Span<BytePos>, name: &str, accept: F) -> Option<Span<BytePos>>
+where
+    F: Fn(&str) -> bool,
+{
+    find_idents(source, span, name, accept).into_iter().next()
+}
+
+/// Returns the spans of all identifiers `name` in `span` for which `accept` returns `true`.
+fn find_idents<F>(source: &str, span: Span<BytePos>, name: &str, accept: F) -> Vec<Span<BytePos>>
+where
+    F: Fn(&str) -> bool,
+{
+    fn is_ident_char(c: char) -> bool {
+        c.is_alphanumeric() || c == '_'
+    }
+
+    let (start, end) = (span.start.to_usize(), span.end.to_usize());
+    let text = match source.get(start..end) {
+        Some(text) => text,
+        None => return Vec::new(),
+    };
+    text.match_indices(name)
+        .filter(|&(i, _)| {
+            !text[..i].ends_with(is_ident_char) &&
+                !text[i + name.len()..].starts_with(is_ident_char) &&
+                accept(&source[..start + i])
+        })
+        .map(|(i, _)| {
+            Span::new(
+                BytePos::from(start + i),
+                BytePos::from(start + i + name.len()),
+            )
+        })
+        .collect()
+}
+
+/// Returns the spans of the identifier of a symbol, assuming that the symbol starts at `start`
+fn ident_span(start: BytePos, symbol: &Symbol) -> Span<BytePos> {
+    Span::new(start, start + BytePos::from(symbol.declared_name().len()))
+}
+
+struct OccurrenceVisitor<'a> {
+    source: &'a str,
+    occurrences: Vec<Occurrence>,
+    /// The types which have been declared so far
+    types: Vec<Symbol>,
+    /// The names of the record types which have been declared so far, as they appear in the
+    /// types of expressions
+    record_types: Vec<Symbol>,
+}
+
+impl<'a> OccurrenceVisitor<'a> {
+    fn push(&mut self, span: Span<BytePos>, reference: Reference, definition: bool) {
+        // Expressions which were not written in the source (such as the implicit prelude) can't be
+        // referred to
+        if span.expansion_id == NO_EXPANSION {
+            self.occurrences.push(Occurrence {
+                span: span,
+                reference: reference,
+                definition: definition,
+            });
+        }
+    }
+
+    /// Arguments are not stored with a span so find them by searching through the source
+    fn args(&mut self, span: Span<BytePos>, args: &[TypedIdent<Symbol>]) {
+        if span.expansion_id != NO_EXPANSION {
+            return;
+        }
+        let mut span = span;
+        for arg in args {
+            if let Some(arg_span) = find_ident(self.source, span, arg.name.declared_name(), |_| {
+                true
+            })
+            {
+                self.push(arg_span, Reference::Symbol(arg.name.clone()), true);
+                span = Span::new(arg_span.end, span.end);
+            }
+        }
+    }
+
+    /// Types are not stored with spans so find the uses of the declared types in the type
+    /// annotations written in `span`
+    fn type_uses(&mut self, span: Span<BytePos>) {
+        if span.expansion_id != NO_EXPANSION {
+            return;
+        }
+        let types = self.types.clone();
+        for typ in types {
+            // Variant constructors may share the name of a type but are preceded by `|`
+            let uses = find_idents(self.source, span, typ.declared_name(), |before| {
+                !before.trim_right().ends_with('|')
+            });
+            for use_span in uses {
+                self.push(use_span, Reference::Symbol(typ.clone()), false);
+            }
+        }
+    }
+
+    /// Returns the record type declared in this module which `typ` refers to
+    fn declared_record(&self, typ: &ArcType) -> Option<Symbol> {
+        let name = match **typ {
+            Type::Alias(ref alias) => &alias.name,
+            Type::Ident(ref id) => id,
+            Type::App(ref head, _) => return self.declared_record(head),
+            _ => return None,
+        };
+        self.record_types.iter().find(|typ| *typ == name).cloned()
+    }
+
+    /// Pushes a use of the field `name` of the record type `typ` if the type is declared in this
+    /// module
+    fn type_field(&mut self, span: Span<BytePos>, typ: &ArcType, name: &str) {
+        if let Some(typ) = self.declared_record(typ) {
+            let reference = Reference::TypeField {
+                typ: typ,
+                name: name.to_string(),
+            };
+            self.push(span, reference, false);
+        }
+    }
+
+    /// Returns the declared type named `name`
+    fn find_type(&self, name: &str) -> Option<Symbol> {
+        self.types
+            .iter()
+            .rev()
+            .find(|typ| typ.declared_name() == name)
+            .cloned()
+    }
+
+    fn pattern(&mut self, pattern: &SpannedPattern<Symbol>, record: Option<&Symbol>) {
+        match pattern.value {
+            Pattern::Ident(ref id) => {
+                self.push(pattern.span, Reference::Symbol(id.name.clone()), true)
+            }
+            Pattern::Record {
+                ref typ,
+                ref types,
+                ref fields,
+                ..
+            } => {
+                for field in fields {
+                    self.type_field(field.name.span, typ, field.name.value.declared_name());
+                }
+                for field in types {
+                    self.push(
+                        field.name.span,
+                        Reference::Field {
+                            record: record.cloned(),
+                            name: field.name.value.declared_name().to_string(),
+                        },
+                        false,
+                    );
+                    if field.name.span.expansion_id == NO_EXPANSION {
+                        let typ = field.value.clone().unwrap_or_else(|| field.name.value.clone());
+                        self.push(field.name.span, Reference::Symbol(typ.clone()), true);
+                        self.types.push(typ);
+                    }
+                }
+                for field in fields {
+                    self.push(
+                        field.name.span,
+                        Reference::Field {
+                            record: record.cloned(),
+                            name: field.name.value.declared_name().to_string(),
+                        },
+                        false,
+                    );
+                    match field.value {
+                        // `{ x }` is given a pattern spanning the entire record
+                        Some(ref value) if value.span == pattern.span => {
+                            if let Pattern::Ident(ref id) = value.value {
+                                let reference = Reference::Symbol(id.name.clone());
+                                self.push(field.name.span, reference, true);
+                            }
+                        }
+                        Some(ref value) => self.pattern(value, None),
+                        None => (),
+                    }
+                }
+            }
+            Pattern::Tuple { ref elems, .. } => for elem in elems {
+                self.pattern(elem, None);
+            },
+            Pattern::Constructor(ref id, ref args) => {
+                self.push(
+                    ident_span(pattern.span.start, &id.name),
+                    Reference::Symbol(id.name.clone()),
+                    false,
+                );
+                for arg in args {
+                    self.pattern(arg, None);
+                }
+            }
+            Pattern::Error => (),
+        }
+    }
+}
+
+impl<'a> Visitor for OccurrenceVisitor<'a> {
+    type Ident = Symbol;
+
+    fn visit_expr(&mut self, expr: &SpannedExpr<Symbol>) {
+        match expr.value {
+            Expr::Ident(ref id) => self.push(expr.span, Reference::Symbol(id.name.clone()), false),
+            Expr::Infix(ref lhs, ref op, ref rhs) => {
+                self.visit_expr(lhs);
+                self.push(op.span, Reference::Symbol(op.value.name.clone()), false);
+                self.visit_expr(rhs);
+            }
+            Expr::Projection(ref record, ref field, _) => {
+                self.visit_expr(record);
+                let end = expr.span.end;
+                let field_span = Span {
+                    start: end - BytePos::from(field.declared_name().len()),
+                    ..expr.span
+                };
+                let record = match record.value {
+                    Expr::Ident(ref id) => {
+                        self.type_field(field_span, &id.typ, field.declared_name());
+                        Some(id.name.clone())
+                    }
+                    _ => None,
+                };
+                self.push(
+                    field_span,
+                    Reference::Field {
+                        record: record,
+                        name: field.declared_name().to_string(),
+                    },
+                    false,
+                );
+            }
+            Expr::Record {
+                ref typ,
+                ref types,
+                ref exprs,
+            } => {
+                for field in exprs {
+                    self.type_field(field.name.span, typ, field.name.value.declared_name());
+                }
+                for field in types {
+                    let name = field.name.value.declared_name().to_string();
+                    if let Some(typ) = self.find_type(&name) {
+                        self.push(field.name.span, Reference::Symbol(typ), false);
+                    }
+                    self.push(
+                        field.name.span,
+                        Reference::Field {
+                            record: None,
+                            name: name,
+                        },
+                        true,
+                    );
+                }
+                for field in exprs {
+                    self.push(
+                        field.name.span,
+                        Reference::Field {
+                            record: None,
+                            name: field.name.value.declared_name().to_string(),
+                        },
+                        true,
+                    );
+                    match field.value {
+                        // `{ x }` is given an identifier spanning the entire record
+                        Some(ref value) if value.span == expr.span => {
+                            if let Expr::Ident(ref id) = value.value {
+                                let reference = Reference::Symbol(id.name.clone());
+                                self.push(field.name.span, reference, false);
+                            }
+                        }
+                        Some(ref value) => self.visit_expr(value),
+                        None => (),
+                    }
+                }
+            }
+            Expr::Match(ref scrutinee, ref alts) => {
+                self.visit_expr(scrutinee);
+                let record = match scrutinee.value {
+                    Expr::Ident(ref id) => Some(id.name.clone()),
+                    _ => None,
+                };
+                for alt in alts {
+                    self.pattern(&alt.pattern, record.as_ref());
+                    self.visit_expr(&alt.expr);
+                }
+            }
+            Expr::Lambda(ref lambda) => {
+                self.args(
+                    Span {
+                        end: lambda.body.span.start,
+                        ..expr.span
+                    },
+                    &lambda.args,
+                );
+                self.visit_expr(&lambda.body);
+            }
+            // Bindings which were not written in the source (such as the implicit prelude) do not
+            // have reliable spans
+            Expr::LetBindings(_, ref body) |
+            Expr::TypeBindings(_, ref body) if expr.span.expansion_id != NO_EXPANSION => {
+                self.visit_expr(body)
+            }
+            Expr::LetBindings(ref bindings, ref body) => {
+                for bind in bindings {
+                    let record = match bind.expr.value {
+                        Expr::Ident(ref id) => Some(id.name.clone()),
+                        _ => None,
+                    };
+                    self.pattern(&bind.name, record.as_ref());
+                    let signature = Span {
+                        start: bind.name.span.end,
+                        end: bind.expr.span.start,
+                        ..bind.name.span
+                    };
+                    self.args(signature, &bind.args);
+                    self.type_uses(signature);
+                    self.visit_expr(&bind.expr);
+                }
+                self.visit_expr(body);
+            }
+            Expr::TypeBindings(ref bindings, ref body) => {
+                // Types declared together may refer to each other
+                for bind in bindings {
+                    self.push(
+                        bind.name.span,
+                        Reference::Symbol(bind.name.value.clone()),
+                        true,
+                    );
+                    self.types.push(bind.name.value.clone());
+                }
+                for bind in bindings {
+                    for (symbol, span) in constructors(self.source, bind) {
+                        self.push(span, Reference::Symbol(symbol), true);
+                    }
+                    if let Some(ref alias) = bind.finalized_alias {
+                        for (name, span) in record_type_fields(self.source, bind) {
+                            let reference = Reference::TypeField {
+                                typ: alias.name.clone(),
+                                name: name,
+                            };
+                            self.push(span, reference, true);
+                        }
+                        self.record_types.push(alias.name.clone());
+                    }
+                    self.type_uses(Span {
+                        start: bind.name.span.end,
+                        ..bind.alias.span
+                    });
+                }
+                self.visit_expr(body);
+            }
+            _ => walk_expr(self, expr),
+        }
+    }
+
+    fn visit_pattern(&mut self, pattern: &SpannedPattern<Symbol>) {
+        walk_pattern(self, &pattern.value)
+    }
+}
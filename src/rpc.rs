@@ -1,25 +1,51 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::error::Error as StdError;
 use std::fmt;
 use std::io::{self, BufRead, Read, Write};
 use std::marker::PhantomData;
+use std::mem;
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
 
-use jsonrpc_core::{Error, ErrorCode, RpcMethodSimple, Params, Value};
+use jsonrpc_core::{Call, Error, ErrorCode, Failure, Id, Output, RpcMethodSimple, Params, Value,
+                   Version};
 use futures::{self, BoxFuture, Future, IntoFuture};
 
 use serde;
-use serde_json::{from_value, to_value, to_string};
+use serde_json::{from_str, from_value, to_value, to_string};
+
+/// Error code which is returned for requests that the client cancelled
+pub const REQUEST_CANCELLED: i64 = -32800;
 
 pub struct ServerError<E> {
+    pub code: ErrorCode,
     pub message: String,
     pub data: Option<E>,
 }
 
+impl<E> ServerError<E> {
+    /// Returns an error which tells the client that the parameters of the request were rejected
+    pub fn invalid_params<M>(message: M) -> ServerError<E>
+    where
+        M: Into<String>,
+    {
+        ServerError {
+            code: ErrorCode::InvalidParams,
+            message: message.into(),
+            data: None,
+        }
+    }
+}
+
 impl<E, D> From<E> for ServerError<D>
 where
     E: fmt::Display,
 {
     fn from(err: E) -> ServerError<D> {
         ServerError {
+            code: ErrorCode::InternalError,
             message: err.to_string(),
             data: None,
         }
@@ -64,6 +90,104 @@ where
         self(param)
     }
 }
+/// Flag which is set when the client cancels the request it belongs to
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst)
+    }
+}
+
+thread_local! {
+    static CURRENT_TOKEN: RefCell<Option<CancellationToken>> = RefCell::new(None);
+}
+
+/// Returns the cancellation token of the request which is being handled on this thread
+pub fn cancellation_token() -> CancellationToken {
+    CURRENT_TOKEN.with(|token| token.borrow().clone().unwrap_or_default())
+}
+
+/// Runs `f` with `token` as the cancellation token of the current thread
+pub fn with_cancellation_token<F, R>(token: CancellationToken, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let previous =
+        CURRENT_TOKEN.with(|current| mem::replace(&mut *current.borrow_mut(), Some(token)));
+    let result = f();
+    CURRENT_TOKEN.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+/// The cancellation tokens of the requests which have not been responded to yet
+#[derive(Clone, Default)]
+pub struct Cancellations(Arc<Mutex<HashMap<Id, CancellationToken>>>);
+
+impl Cancellations {
+    /// Cancels the request named by `json` if it is a `$/cancelRequest` notification. Returns
+    /// `true` if the message was a cancellation and needs no further handling.
+    pub fn cancel_message(&self, json: &str) -> bool {
+        let params = match from_str(json) {
+            Ok(Call::Notification(ref notification))
+                if notification.method == "$/cancelRequest" => notification.params.clone(),
+            _ => return false,
+        };
+        let id = match params {
+            Some(Params::Map(mut map)) => map.remove("id").and_then(|id| from_value(id).ok()),
+            _ => None,
+        };
+        match id.and_then(|id: Id| self.0.lock().unwrap().get(&id).cloned()) {
+            Some(token) => token.cancel(),
+            None => debug!("Unable to cancel request: {}", json),
+        }
+        true
+    }
+
+    /// Creates a cancellation token for `json` if it is a request
+    pub fn register_message(&self, json: &str) -> Option<(Id, CancellationToken)> {
+        match from_str(json) {
+            Ok(Call::MethodCall(call)) => {
+                let token = CancellationToken::default();
+                self.0.lock().unwrap().insert(call.id.clone(), token.clone());
+                Some((call.id, token))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn remove(&self, id: &Id) {
+        self.0.lock().unwrap().remove(id);
+    }
+}
+
+fn request_cancelled() -> Error {
+    Error {
+        code: ErrorCode::ServerError(REQUEST_CANCELLED),
+        message: "Request cancelled".into(),
+        data: None,
+    }
+}
+
+/// Returns the response to the request `id` whose handler failed unexpectedly with `message`
+pub fn internal_error_response(id: Id, message: &str) -> String {
+    let output = Output::Failure(Failure {
+        jsonrpc: Some(Version::V2),
+        error: Error {
+            code: ErrorCode::InternalError,
+            message: format!("Internal error: {}", message),
+            data: None,
+        },
+        id: id,
+    });
+    to_string(&output).expect("Output")
+}
+
 pub struct ServerCommand<T, P>(pub T, PhantomData<fn(P)>);
 
 impl<T, P> ServerCommand<T, P> {
@@ -78,6 +202,10 @@ where
     P: for<'de> serde::Deserialize<'de> + 'static,
 {
     fn call(&self, param: Params) -> BoxFuture<Value, Error> {
+        let token = cancellation_token();
+        if token.is_cancelled() {
+            return futures::failed(request_cancelled()).boxed();
+        }
         let value = match param {
             Params::Map(map) => Value::Object(map),
             Params::Array(arr) => Value::Array(arr),
@@ -87,7 +215,9 @@ where
             Ok(value) => {
                 return self.0
                     .execute(value)
-                    .then(|result| match result {
+                    .then(move |result| match result {
+                        // The client ignores the result of a cancelled request
+                        _ if token.is_cancelled() => Err(request_cancelled()).into_future(),
                         Ok(value) => {
                             Ok(
                                 to_value(&value).expect("result data could not be serialized"),
@@ -95,7 +225,7 @@ where
                         }
                         Err(error) => {
                             Err(Error {
-                                code: ErrorCode::InternalError,
+                                code: error.code,
                                 message: error.message,
                                 data: error.data.as_ref().map(
                                     |v| to_value(v).expect("error data could not be serialized"),
@@ -168,3 +298,81 @@ where
     try!(output.flush());
     Ok(())
 }
+
+enum Message {
+    Text(String),
+    Close,
+}
+
+/// Sends messages to the client through a `MessageWriter`
+#[derive(Clone)]
+pub struct ClientSender(mpsc::Sender<Message>);
+
+impl ClientSender {
+    pub fn send_message_str(&self, message: String) {
+        // Messages sent after the writer has been closed are dropped
+        let _ = self.0.send(Message::Text(message));
+    }
+
+    pub fn send_message<T>(&self, value: &T)
+    where
+        T: serde::Serialize,
+    {
+        self.send_message_str(to_string(value).unwrap())
+    }
+
+    pub fn send_notification<P>(&self, method: &str, params: &P)
+    where
+        P: serde::Serialize,
+    {
+        self.send_message(&json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+        }))
+    }
+}
+
+/// Owns the output to the client and writes every message on a single thread so that messages
+/// are never interleaved
+pub struct MessageWriter {
+    sender: ClientSender,
+    thread: JoinHandle<()>,
+}
+
+impl MessageWriter {
+    pub fn new<W>(mut output: W) -> MessageWriter
+    where
+        W: Write + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("writer".to_string())
+            .spawn(move || for message in receiver {
+                match message {
+                    Message::Text(message) => {
+                        if let Err(err) = write_message_str(&mut output, &message) {
+                            error!("Unable to write message: {}", err);
+                            break;
+                        }
+                    }
+                    Message::Close => break,
+                }
+            })
+            .unwrap();
+        MessageWriter {
+            sender: ClientSender(sender),
+            thread: thread,
+        }
+    }
+
+    pub fn sender(&self) -> ClientSender {
+        self.sender.clone()
+    }
+
+    /// Writes the messages which have already been sent and stops the writer
+    pub fn close(self) {
+        let _ = self.sender.0.send(Message::Close);
+        let _ = self.thread.join();
+    }
+}
@@ -0,0 +1,78 @@
+extern crate gluon_language_server;
+extern crate languageserver_types;
+
+extern crate jsonrpc_core;
+extern crate serde_json;
+extern crate serde;
+extern crate url;
+
+#[allow(unused)]
+mod support;
+
+use std::process::Command;
+use std::str;
+
+use languageserver_types::PublishDiagnosticsParams;
+
+#[test]
+fn check_without_errors() {
+    let output = Command::new(support::server_path())
+        .args(&["check", "tests/module.glu"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(str::from_utf8(&output.stdout).unwrap(), "");
+}
+
+#[test]
+fn check_type_error() {
+    let dir = support::TempDir::new("check_type_error");
+    let path = &dir.write("check_type_error.glu", "let x : Int = \"\"\nx\n");
+    let human = Command::new(support::server_path())
+        .args(&["check", path])
+        .output()
+        .unwrap();
+    let json = Command::new(support::server_path())
+        .args(&["check", "--format", "json", path])
+        .output()
+        .unwrap();
+
+    assert_eq!(human.status.code(), Some(1));
+    let stdout = str::from_utf8(&human.stdout).unwrap();
+    assert!(stdout.starts_with("error: "), "{}", stdout);
+    assert!(
+        stdout.contains(&format!(" --> {}:1:5\n", path)),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("1 | let x : Int = \"\"\n"), "{}", stdout);
+
+    assert_eq!(json.status.code(), Some(1));
+    let diagnostics: Vec<PublishDiagnosticsParams> = serde_json::from_slice(&json.stdout).unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].uri, support::test_url(path));
+    assert_eq!(diagnostics[0].diagnostics.len(), 1);
+}
+
+#[test]
+fn check_path_with_dot() {
+    let dir = support::TempDir::new("check_path_with_dot");
+    let path = &dir.write("check.dir/bad.glu", "let x = \"😀\" in \"\" + 1\n");
+    let human = Command::new(support::server_path())
+        .args(&["check", path])
+        .output()
+        .unwrap();
+
+    assert_eq!(human.status.code(), Some(1));
+    let stdout = str::from_utf8(&human.stdout).unwrap();
+    assert!(
+        stdout.contains(&format!(" --> {}:1:16\n", path)),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("1 | let x = \"😀\" in \"\" + 1\n  |                ^^^^^^\n"),
+        "{}",
+        stdout
+    );
+}
@@ -3,6 +3,7 @@ extern crate gluon_language_server;
 extern crate languageserver_types;
 
 extern crate jsonrpc_core;
+#[macro_use]
 extern crate serde_json;
 extern crate serde;
 extern crate url;
@@ -13,10 +14,11 @@ mod support;
 
 use std::io::Write;
 
+use serde_json::Value;
 use url::Url;
 
-use languageserver_types::{CompletionItem, CompletionItemKind, Position, TextDocumentIdentifier,
-                           TextDocumentPositionParams};
+use languageserver_types::{CompletionItem, CompletionItemKind, Position, Range,
+                           TextDocumentIdentifier, TextDocumentPositionParams, TextEdit};
 
 use gluon_language_server::CompletionData;
 
@@ -113,7 +115,7 @@ fn prelude_completion() {
         vec![
             CompletionItem {
                 label: "not".into(),
-                kind: Some(CompletionItemKind::Variable),
+                kind: Some(CompletionItemKind::Function),
                 detail: Some("std.types.Bool -> std.types.Bool".into()),
                 ..CompletionItem::default()
             },
@@ -188,10 +190,363 @@ r.
         vec![
             CompletionItem {
                 label: "abc".into(),
-                kind: Some(CompletionItemKind::Variable),
+                kind: Some(CompletionItemKind::Field),
                 detail: Some("Int".into()),
                 ..CompletionItem::default()
             },
         ]
     );
 }
+
+/// Returns the labels and kinds of the completions at `position`, keeping the kinds which
+/// `languageserver_types` does not know about
+fn completion_kinds(text: &str, position: Position) -> Vec<(String, u64)> {
+    let completions: Vec<Value> = support::send_rpc(|mut stdin| {
+        support::did_open(stdin, "test", text);
+
+        completion(stdin, 1, "test", position)
+    });
+    completions
+        .iter()
+        .map(|item| {
+            (
+                item["label"].as_str().unwrap().to_string(),
+                item["kind"].as_u64().unwrap(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn completion_kinds_from_types() {
+    let text = r#"
+type Shape = | Circle Int | Square Int
+type Point = { x : Int, y : Int }
+let module = import! "tests/module.glu"
+let shapes = { Shape, Point, circle = Circle 1, area = \s -> 1 }
+"#;
+    let field_text = format!("{}shapes.", text);
+    let kinds = completion_kinds(
+        &field_text,
+        Position {
+            line: 5,
+            character: 7,
+        },
+    );
+    assert_eq!(
+        kinds,
+        vec![
+            ("Point".to_string(), 22),
+            ("Shape".to_string(), CompletionItemKind::Class as u64),
+            ("area".to_string(), CompletionItemKind::Function as u64),
+            ("circle".to_string(), CompletionItemKind::Field as u64),
+        ]
+    );
+
+    let constructor_text = format!("{}Ci", text);
+    let kinds = completion_kinds(
+        &constructor_text,
+        Position {
+            line: 5,
+            character: 2,
+        },
+    );
+    assert_eq!(kinds, vec![("Circle".to_string(), 20)]);
+
+    let module_text = format!("{}mod", text);
+    let kinds = completion_kinds(
+        &module_text,
+        Position {
+            line: 5,
+            character: 3,
+        },
+    );
+    assert_eq!(kinds, vec![("module".to_string(), CompletionItemKind::Module as u64)]);
+}
+
+#[test]
+fn import_path_completion() {
+    let completions: Vec<CompletionItem> = support::send_rpc(|mut stdin| {
+        support::did_open(stdin, "test", "let m = import! \"tests/mod");
+
+        completion(
+            stdin,
+            1,
+            "test",
+            Position {
+                line: 0,
+                character: 26,
+            },
+        )
+    });
+    let completions = remove_completion_data(completions);
+    assert_eq!(
+        completions,
+        vec![
+            CompletionItem {
+                label: "tests/module.glu".into(),
+                kind: Some(CompletionItemKind::Module),
+                text_edit: Some(TextEdit {
+                    range: Range {
+                        start: Position {
+                            line: 0,
+                            character: 17,
+                        },
+                        end: Position {
+                            line: 0,
+                            character: 26,
+                        },
+                    },
+                    new_text: "tests/module.glu".into(),
+                }),
+                ..CompletionItem::default()
+            },
+        ]
+    );
+}
+
+#[test]
+fn std_import_completion() {
+    let completions: Vec<CompletionItem> = support::send_rpc(|mut stdin| {
+        support::did_open(stdin, "test", "import! \"std/");
+
+        completion(
+            stdin,
+            1,
+            "test",
+            Position {
+                line: 0,
+                character: 13,
+            },
+        )
+    });
+
+    // `std.types` is loaded by the prelude so its type is known without loading it
+    let types = completions
+        .iter()
+        .find(|item| item.label == "std/types.glu")
+        .expect("std/types.glu");
+    assert!(types.detail.is_some());
+
+    let map = completions
+        .iter()
+        .find(|item| item.label == "std/map.glu")
+        .expect("std/map.glu");
+    assert_eq!(map.detail, None);
+
+    let resolved: CompletionItem = support::send_rpc(|mut stdin| {
+        support::did_open(stdin, "test", "import! \"std/");
+
+        resolve(stdin, 2, map)
+    });
+    assert!(
+        resolved.detail.as_ref().map_or(false, |detail| detail.contains("make")),
+        "{:?}",
+        resolved.detail
+    );
+
+    // Resolving the item must not load the module
+    let mut server = support::Server::spawn();
+    support::did_open(&mut server.stdin, "test", "import! \"std/");
+    resolve(&mut server.stdin, 2, map);
+    let _: CompletionItem = server.wait_for_response(2);
+    completion(
+        &mut server.stdin,
+        3,
+        "test",
+        Position {
+            line: 0,
+            character: 13,
+        },
+    );
+    let completions: Vec<CompletionItem> = server.wait_for_response(3);
+    server.exit();
+
+    let map = completions
+        .iter()
+        .find(|item| item.label == "std/map.glu")
+        .expect("std/map.glu");
+    assert_eq!(map.detail, None);
+}
+
+/// Returns the labels and details of the completions at `position`
+fn completion_details(text: &str, position: Position) -> Vec<(String, Option<String>)> {
+    let completions: Vec<CompletionItem> = support::send_rpc(|mut stdin| {
+        support::did_open(stdin, "test", text);
+
+        completion(stdin, 1, "test", position)
+    });
+    completions
+        .into_iter()
+        .map(|item| {
+            assert_eq!(item.kind, Some(CompletionItemKind::Field));
+            (item.label, item.detail)
+        })
+        .collect()
+}
+
+#[test]
+fn record_field_completion() {
+    let text = r#"
+type Point = { x : Int, y : Int, name : String }
+let f p : Point -> Int = p.x
+"#;
+    let field = |name: &str, typ: &str| (name.to_string(), Some(typ.to_string()));
+
+    let construction_text = format!("{}let p : Point = {{ x = 1, }}\np", text);
+    let details = completion_details(
+        &construction_text,
+        Position {
+            line: 3,
+            character: 25,
+        },
+    );
+    assert_eq!(details, vec![field("name", "String"), field("y", "Int")]);
+
+    let argument_text = format!("{}f {{ y = 1, n }}", text);
+    let details = completion_details(
+        &argument_text,
+        Position {
+            line: 3,
+            character: 12,
+        },
+    );
+    assert_eq!(details, vec![field("name", "String")]);
+
+    let pattern_text = format!("{}let {{ y, }} = {{ x = 1, y = 2, name = \"\" }}\ny", text);
+    let details = completion_details(
+        &pattern_text,
+        Position {
+            line: 3,
+            character: 9,
+        },
+    );
+    assert_eq!(details, vec![field("name", "String"), field("x", "Int")]);
+}
+
+fn initialize_with_snippets<W: ?Sized>(stdin: &mut W)
+where
+    W: Write,
+{
+    let initialize = support::method_call(
+        "initialize",
+        1,
+        json!({
+            "processId": null,
+            "capabilities": {
+                "textDocument": {
+                    "completion": { "completionItem": { "snippetSupport": true } },
+                },
+            },
+        }),
+    );
+    support::write_message(stdin, initialize).unwrap();
+}
+
+/// Returns the completions at the end of `text` when the client supports snippets
+fn snippet_completions(text: &str) -> Vec<Value> {
+    let position = Position {
+        line: text.lines().count() as u64 - 1,
+        character: text.lines().last().unwrap().len() as u64,
+    };
+    support::send_rpc(|mut stdin| {
+        initialize_with_snippets(stdin);
+        support::did_open(stdin, "test", text);
+
+        completion(stdin, 2, "test", position)
+    })
+}
+
+fn find_label<'a>(completions: &'a [Value], label: &str) -> &'a Value {
+    completions
+        .iter()
+        .find(|item| item["label"] == label)
+        .unwrap_or_else(|| panic!("No completion for `{}` in {:?}", label, completions))
+}
+
+#[test]
+fn function_snippet_completion() {
+    let text = r#"
+let add x y : Int -> Int -> Int = x + y
+let apply f o : (Int -> Int) -> Option Int -> Int = 0
+let abc = 1
+a"#;
+    let completions = snippet_completions(text);
+
+    let add = find_label(&completions, "add");
+    assert_eq!(add["insertText"], "add ${1:int} ${2:int}");
+    assert_eq!(add["insertTextFormat"], 2);
+
+    let apply = find_label(&completions, "apply");
+    assert_eq!(apply["insertText"], "apply ${1:f} ${2:option}");
+
+    let abc = find_label(&completions, "abc");
+    assert_eq!(abc["insertText"], Value::Null);
+    assert_eq!(abc["insertTextFormat"], Value::Null);
+}
+
+#[test]
+fn keyword_snippet_completion() {
+    let completions = snippet_completions("let x = 1\nma");
+
+    let keyword = find_label(&completions, "match");
+    assert_eq!(keyword["kind"], CompletionItemKind::Keyword as u64);
+    assert_eq!(keyword["insertText"], "match ${1:value} with\n| ${2:pattern} -> $0");
+    assert_eq!(keyword["insertTextFormat"], 2);
+    assert!(completions.iter().all(|item| item["label"] != "let"));
+}
+
+#[test]
+fn resolve_module_without_loading_its_imports() {
+    let position = Position {
+        line: 0,
+        character: 13,
+    };
+    let mut server = support::Server::spawn();
+    support::did_open(&mut server.stdin, "test", "import! \"std/");
+    completion(&mut server.stdin, 1, "test", position);
+    let completions: Vec<CompletionItem> = server.wait_for_response(1);
+    let test = completions
+        .iter()
+        .find(|item| item.label == "std/test.glu")
+        .expect("std/test.glu");
+
+    resolve(&mut server.stdin, 2, test);
+    let resolved: CompletionItem = server.wait_for_response(2);
+    assert!(
+        resolved.detail.as_ref().map_or(false, |detail| detail.contains("assert_eq")),
+        "{:?}",
+        resolved.detail
+    );
+
+    completion(&mut server.stdin, 3, "test", position);
+    let completions: Vec<CompletionItem> = server.wait_for_response(3);
+    server.exit();
+
+    // The modules which `std.test` imports are not loaded by resolving it either
+    for label in &["std/string.glu", "std/writer.glu"] {
+        let item = completions
+            .iter()
+            .find(|item| item.label == *label)
+            .expect(label);
+        assert_eq!(item.detail, None, "{}", label);
+    }
+}
+
+#[test]
+fn import_completion_in_parentheses() {
+    let completions: Vec<CompletionItem> = support::send_rpc(|mut stdin| {
+        support::did_open(stdin, "test", "import!(\"std/");
+
+        completion(
+            stdin,
+            1,
+            "test",
+            Position {
+                line: 0,
+                character: 13,
+            },
+        )
+    });
+    assert!(completions.iter().any(|item| item.label == "std/map.glu"));
+}
@@ -0,0 +1,241 @@
+extern crate gluon_language_server;
+extern crate languageserver_types;
+
+extern crate jsonrpc_core;
+extern crate serde_json;
+extern crate serde;
+extern crate url;
+
+mod support;
+
+use std::fs;
+use std::io::Write;
+
+use url::Url;
+
+use languageserver_types::{Location, Position, Range, TextDocumentPositionParams,
+                           TextDocumentIdentifier};
+
+fn definition<W: ?Sized>(stdin: &mut W, id: u64, uri: &str, position: Position)
+where
+    W: Write,
+{
+    let definition = support::method_call(
+        "textDocument/definition",
+        id,
+        TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier { uri: support::test_url(uri) },
+            position: position,
+        },
+    );
+
+    support::write_message(stdin, definition).unwrap();
+}
+
+fn range(start_line: u64, start_character: u64, end_line: u64, end_character: u64) -> Range {
+    Range {
+        start: Position {
+            line: start_line,
+            character: start_character,
+        },
+        end: Position {
+            line: end_line,
+            character: end_character,
+        },
+    }
+}
+
+fn module_url() -> Url {
+    Url::from_file_path(fs::canonicalize("tests/module.glu").unwrap()).unwrap()
+}
+
+#[test]
+fn definition_of_let_binding() {
+    let location: Location = support::send_rpc(|mut stdin| {
+        support::did_open(stdin, "test", "let x = 1\nlet y = x\ny");
+
+        definition(
+            stdin,
+            2,
+            "test",
+            Position {
+                line: 1,
+                character: 8,
+            },
+        );
+    });
+
+    assert_eq!(
+        location,
+        Location {
+            uri: support::test_url("test"),
+            range: range(0, 4, 0, 5),
+        }
+    );
+}
+
+#[test]
+fn definition_of_function_argument() {
+    let location: Location = support::send_rpc(|mut stdin| {
+        support::did_open(stdin, "test", "let f a b = b\nf 1 2");
+
+        definition(
+            stdin,
+            2,
+            "test",
+            Position {
+                line: 0,
+                character: 12,
+            },
+        );
+    });
+
+    assert_eq!(
+        location,
+        Location {
+            uri: support::test_url("test"),
+            range: range(0, 8, 0, 9),
+        }
+    );
+}
+
+#[test]
+fn definition_of_lambda_argument() {
+    let location: Location = support::send_rpc(|mut stdin| {
+        support::did_open(stdin, "test", "let f = \\x -> x\nf 1");
+
+        definition(
+            stdin,
+            2,
+            "test",
+            Position {
+                line: 0,
+                character: 14,
+            },
+        );
+    });
+
+    assert_eq!(
+        location,
+        Location {
+            uri: support::test_url("test"),
+            range: range(0, 9, 0, 10),
+        }
+    );
+}
+
+#[test]
+fn definition_of_variant_constructor() {
+    let location: Location = support::send_rpc(|mut stdin| {
+        support::did_open(
+            stdin,
+            "test",
+            "type Shape = | Circle Int | Square Int\nSquare 2",
+        );
+
+        definition(
+            stdin,
+            2,
+            "test",
+            Position {
+                line: 1,
+                character: 2,
+            },
+        );
+    });
+
+    assert_eq!(
+        location,
+        Location {
+            uri: support::test_url("test"),
+            range: range(0, 28, 0, 34),
+        }
+    );
+}
+
+#[test]
+fn definition_of_imported_module() {
+    let location: Location = support::send_rpc(|mut stdin| {
+        support::did_open(
+            stdin,
+            "test",
+            "let m = import! \"tests/module.glu\"\nm",
+        );
+
+        definition(
+            stdin,
+            2,
+            "test",
+            Position {
+                line: 0,
+                character: 10,
+            },
+        );
+    });
+
+    assert_eq!(
+        location,
+        Location {
+            uri: module_url(),
+            range: Range::default(),
+        }
+    );
+}
+
+#[test]
+fn definition_of_imported_field() {
+    let location: Location = support::send_rpc(|mut stdin| {
+        support::did_open(
+            stdin,
+            "test",
+            "let m = import! \"tests/module.glu\"\nm.id 1",
+        );
+
+        definition(
+            stdin,
+            2,
+            "test",
+            Position {
+                line: 1,
+                character: 3,
+            },
+        );
+    });
+
+    assert_eq!(
+        location,
+        Location {
+            uri: module_url(),
+            range: range(2, 2, 2, 4),
+        }
+    );
+}
+
+#[test]
+fn definition_of_destructured_import() {
+    let location: Location = support::send_rpc(|mut stdin| {
+        support::did_open(
+            stdin,
+            "test",
+            "let { one } = import! \"tests/module.glu\"\none",
+        );
+
+        definition(
+            stdin,
+            2,
+            "test",
+            Position {
+                line: 1,
+                character: 1,
+            },
+        );
+    });
+
+    assert_eq!(
+        location,
+        Location {
+            uri: module_url(),
+            range: range(2, 6, 2, 9),
+        }
+    );
+}
@@ -2,6 +2,7 @@ extern crate gluon_language_server;
 extern crate languageserver_types;
 
 extern crate jsonrpc_core;
+#[macro_use]
 extern crate serde_json;
 extern crate serde;
 extern crate url;
@@ -9,6 +10,8 @@ extern crate url;
 #[allow(unused)]
 mod support;
 
+use serde_json::Value;
+
 use languageserver_types::{DiagnosticSeverity, Position, PublishDiagnosticsParams, Range};
 
 #[test]
@@ -19,7 +22,7 @@ fn type_error() {
 "#;
         support::did_open(stdin, "test", text);
     });
-    assert_eq!(diagnostic.uri, support::test_url("test.glu"));
+    assert_eq!(diagnostic.uri, support::test_url("test"));
     assert_eq!(diagnostic.diagnostics.len(), 1);
     let error = &diagnostic.diagnostics[0];
     assert_eq!(error.severity, Some(DiagnosticSeverity::Error));
@@ -37,3 +40,78 @@ fn type_error() {
         }
     );
 }
+
+#[test]
+fn utf16_positions() {
+    let diagnostic: PublishDiagnosticsParams = support::send_rpc(|mut stdin| {
+        let text = r#"
+let x = "😀" in "" + 1
+"#;
+        support::did_open(stdin, "test", text);
+    });
+    assert_eq!(diagnostic.diagnostics.len(), 1);
+    assert_eq!(
+        diagnostic.diagnostics[0].range,
+        Range {
+            start: Position {
+                line: 1,
+                character: 16,
+            },
+            end: Position {
+                line: 1,
+                character: 22,
+            },
+        }
+    );
+}
+
+fn initialize_with_utf8<W: ?Sized>(stdin: &mut W)
+where
+    W: ::std::io::Write,
+{
+    let initialize = support::method_call(
+        "initialize",
+        1,
+        json!({
+            "processId": null,
+            "capabilities": {
+                "general": { "positionEncodings": ["utf-8", "utf-16"] },
+            },
+        }),
+    );
+    support::write_message(stdin, initialize).unwrap();
+}
+
+#[test]
+fn negotiate_utf8_positions() {
+    let result: Value = support::send_rpc(|mut stdin| initialize_with_utf8(stdin));
+    assert_eq!(result["capabilities"]["positionEncoding"], "utf-8");
+
+    // Clients wait for the response to `initialize` before sending any other message
+    let mut server = support::Server::spawn();
+    initialize_with_utf8(&mut server.stdin);
+    let _: Value = server.wait_for_response(1);
+    let text = r#"
+let x = "😀" in "" + 1
+"#;
+    support::did_open(&mut server.stdin, "test.glu", text);
+    let diagnostics =
+        server.wait_for_diagnostics(&support::test_url("test.glu"), |diagnostics| {
+            !diagnostics.is_empty()
+        });
+    server.exit();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(
+        diagnostics[0].range,
+        Range {
+            start: Position {
+                line: 1,
+                character: 18,
+            },
+            end: Position {
+                line: 1,
+                character: 24,
+            },
+        }
+    );
+}
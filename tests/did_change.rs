@@ -0,0 +1,97 @@
+extern crate gluon_language_server;
+extern crate languageserver_types;
+
+extern crate jsonrpc_core;
+extern crate serde_json;
+extern crate serde;
+extern crate url;
+
+#[allow(unused)]
+mod support;
+
+use std::io::Write;
+
+use languageserver_types::{DidChangeConfigurationParams, DidChangeTextDocumentParams,
+                           DocumentFormattingParams, FormattingOptions, Position, Range,
+                           TextDocumentContentChangeEvent, TextDocumentIdentifier, TextEdit,
+                           VersionedTextDocumentIdentifier};
+
+fn did_change<W: ?Sized>(stdin: &mut W, uri: &str, changes: Vec<TextDocumentContentChangeEvent>)
+where
+    W: Write,
+{
+    let did_change = support::notification(
+        "textDocument/didChange",
+        DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier {
+                uri: support::test_url(uri),
+                version: 2,
+            },
+            content_changes: changes,
+        },
+    );
+    support::write_message(stdin, did_change).unwrap();
+}
+
+fn insert(line: u64, character: u64, text: &str) -> TextDocumentContentChangeEvent {
+    let position = Position {
+        line: line,
+        character: character,
+    };
+    TextDocumentContentChangeEvent {
+        range: Some(Range {
+            start: position,
+            end: position,
+        }),
+        range_length: Some(0),
+        text: text.into(),
+    }
+}
+
+/// Delays the checking of changed documents past the requests in a test
+fn debounce<W: ?Sized>(stdin: &mut W)
+where
+    W: Write,
+{
+    let did_change_configuration = support::notification(
+        "workspace/didChangeConfiguration",
+        DidChangeConfigurationParams {
+            settings: serde_json::from_str(r#"{ "gluon": { "debounce": 2000 } }"#).unwrap(),
+        },
+    );
+    support::write_message(stdin, did_change_configuration).unwrap();
+}
+
+fn format<W: ?Sized>(stdin: &mut W, id: u64, uri: &str)
+where
+    W: Write,
+{
+    let format = support::method_call(
+        "textDocument/formatting",
+        id,
+        DocumentFormattingParams {
+            text_document: TextDocumentIdentifier { uri: support::test_url(uri) },
+            options: FormattingOptions {
+                tab_size: 4,
+                insert_spaces: true,
+                properties: Default::default(),
+            },
+        },
+    );
+    support::write_message(stdin, format).unwrap();
+}
+
+#[test]
+fn failed_change_is_not_partially_applied() {
+    // Formatting uses the text of the document before it has been checked, so it returns edits
+    // only if the first change was applied
+    let edits: Vec<TextEdit> = support::send_rpc(|mut stdin| {
+        debounce(stdin);
+        support::did_open(stdin, "test", "let x = 1\nx\n");
+        did_change(stdin, "test", vec![insert(0, 7, "  "), insert(100, 0, "y")]);
+
+        format(stdin, 2, "test")
+    });
+
+    assert_eq!(edits, vec![]);
+}
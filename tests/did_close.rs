@@ -0,0 +1,72 @@
+extern crate gluon_language_server;
+extern crate languageserver_types;
+
+extern crate jsonrpc_core;
+extern crate serde_json;
+extern crate serde;
+extern crate url;
+
+mod support;
+
+use languageserver_types::{DidCloseTextDocumentParams, DocumentSymbolParams,
+                           PublishDiagnosticsParams, SymbolInformation, TextDocumentIdentifier};
+
+fn did_close<W: ?Sized>(stdin: &mut W, uri: &str)
+where
+    W: std::io::Write,
+{
+    let did_close = support::notification(
+        "textDocument/didClose",
+        DidCloseTextDocumentParams {
+            text_document: TextDocumentIdentifier { uri: support::test_url(uri) },
+        },
+    );
+    support::write_message(stdin, did_close).unwrap();
+}
+
+#[test]
+fn close_clears_diagnostics() {
+    let diagnostic: PublishDiagnosticsParams = support::send_rpc(|mut stdin| {
+        support::did_open(stdin, "test", r#""" + 1"#);
+        did_close(stdin, "test");
+    });
+    assert_eq!(diagnostic.uri, support::test_url("test"));
+    assert_eq!(diagnostic.diagnostics, vec![]);
+}
+
+#[test]
+fn close_reverts_to_file_on_disk() {
+    let symbols: Vec<SymbolInformation> = support::send_rpc(|mut stdin| {
+        support::did_open(stdin, "tests/module.glu", "let unsaved = 1\n{ unsaved }\n");
+        did_close(stdin, "tests/module.glu");
+
+        let document_symbol = support::method_call(
+            "textDocument/documentSymbol",
+            2,
+            DocumentSymbolParams {
+                text_document: TextDocumentIdentifier {
+                    uri: support::test_url("tests/module.glu"),
+                },
+            },
+        );
+        support::write_message(stdin, document_symbol).unwrap();
+    });
+
+    let names: Vec<_> = symbols.iter().map(|symbol| &symbol.name[..]).collect();
+    assert!(names.contains(&"id"), "{:?}", names);
+    assert!(!names.contains(&"unsaved"), "{:?}", names);
+}
+
+#[test]
+fn close_clears_the_published_errors() {
+    // The errors must be published at the url the document was opened with for closing it to
+    // clear them
+    let mut server = support::Server::spawn();
+    let uri = support::test_url("test");
+    support::did_open(&mut server.stdin, "test", r#""" + 1"#);
+    server.wait_for_diagnostics(&uri, |diagnostics| !diagnostics.is_empty());
+
+    did_close(&mut server.stdin, "test");
+    server.wait_for_diagnostics(&uri, |diagnostics| diagnostics.is_empty());
+    server.exit();
+}
@@ -0,0 +1,57 @@
+extern crate gluon_language_server;
+extern crate languageserver_types;
+
+extern crate jsonrpc_core;
+extern crate serde_json;
+extern crate serde;
+extern crate url;
+
+mod support;
+
+use languageserver_types::{DocumentHighlight, DocumentHighlightKind, Position, Range,
+                           TextDocumentIdentifier, TextDocumentPositionParams};
+
+fn highlight(line: u64, start: u64, end: u64, kind: DocumentHighlightKind) -> DocumentHighlight {
+    DocumentHighlight {
+        range: Range {
+            start: Position {
+                line: line,
+                character: start,
+            },
+            end: Position {
+                line: line,
+                character: end,
+            },
+        },
+        kind: Some(kind),
+    }
+}
+
+#[test]
+fn highlight_binding() {
+    let highlights: Vec<DocumentHighlight> = support::send_rpc(|mut stdin| {
+        support::did_open(stdin, "test", "let x = 1\nlet f y = x\nlet x = f x\nx");
+
+        let document_highlight = support::method_call(
+            "textDocument/documentHighlight",
+            2,
+            TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri: support::test_url("test") },
+                position: Position {
+                    line: 1,
+                    character: 10,
+                },
+            },
+        );
+        support::write_message(stdin, document_highlight).unwrap();
+    });
+
+    assert_eq!(
+        highlights,
+        vec![
+            highlight(0, 4, 5, DocumentHighlightKind::Write),
+            highlight(1, 10, 11, DocumentHighlightKind::Read),
+            highlight(2, 10, 11, DocumentHighlightKind::Read),
+        ]
+    );
+}
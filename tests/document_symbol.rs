@@ -0,0 +1,76 @@
+extern crate gluon_language_server;
+extern crate languageserver_types;
+
+extern crate jsonrpc_core;
+extern crate serde_json;
+extern crate serde;
+extern crate url;
+
+mod support;
+
+use languageserver_types::{DocumentSymbolParams, Location, Position, Range, SymbolInformation,
+                           SymbolKind, TextDocumentIdentifier};
+
+fn symbol(
+    name: &str,
+    kind: SymbolKind,
+    start: (u64, u64),
+    end: (u64, u64),
+    container: Option<&str>,
+) -> SymbolInformation {
+    SymbolInformation {
+        name: name.into(),
+        kind: kind,
+        location: Location {
+            uri: support::test_url("test"),
+            range: Range {
+                start: Position {
+                    line: start.0,
+                    character: start.1,
+                },
+                end: Position {
+                    line: end.0,
+                    character: end.1,
+                },
+            },
+        },
+        container_name: container.map(String::from),
+    }
+}
+
+#[test]
+fn document_symbols() {
+    let symbols: Vec<SymbolInformation> = support::send_rpc(|mut stdin| {
+        support::did_open(
+            stdin,
+            "test",
+            r#"let id x = x
+type Shape = | Circle Int | Square Int
+let one = 1
+{ id, one }
+"#,
+        );
+
+        let document_symbol = support::method_call(
+            "textDocument/documentSymbol",
+            2,
+            DocumentSymbolParams {
+                text_document: TextDocumentIdentifier { uri: support::test_url("test") },
+            },
+        );
+        support::write_message(stdin, document_symbol).unwrap();
+    });
+
+    assert_eq!(
+        symbols,
+        vec![
+            symbol("id", SymbolKind::Function, (0, 4), (0, 12), None),
+            symbol("Shape", SymbolKind::Enum, (1, 5), (1, 38), None),
+            symbol("Circle", SymbolKind::Constructor, (1, 15), (1, 21), Some("Shape")),
+            symbol("Square", SymbolKind::Constructor, (1, 28), (1, 34), Some("Shape")),
+            symbol("one", SymbolKind::Variable, (2, 4), (2, 11), None),
+            symbol("id", SymbolKind::Field, (3, 2), (3, 4), None),
+            symbol("one", SymbolKind::Field, (3, 6), (3, 9), None),
+        ]
+    );
+}
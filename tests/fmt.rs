@@ -0,0 +1,66 @@
+extern crate gluon;
+extern crate gluon_language_server;
+extern crate languageserver_types;
+
+extern crate jsonrpc_core;
+extern crate serde_json;
+extern crate serde;
+extern crate url;
+
+#[allow(unused)]
+mod support;
+
+use std::fs::File;
+use std::io::Read;
+use std::process::Command;
+use std::str;
+
+use gluon::parser::format_expr;
+
+const UNFORMATTED: &str = "let x   =  1\nx\n";
+
+#[test]
+fn fmt_check() {
+    let dir = support::TempDir::new("fmt_check");
+    let path = &dir.write("fmt_check.glu", UNFORMATTED);
+    let output = Command::new(support::server_path())
+        .args(&["fmt", "--check", path])
+        .output()
+        .unwrap();
+    let mut source = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut source))
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(source, UNFORMATTED);
+    let stdout = str::from_utf8(&output.stdout).unwrap();
+    assert!(
+        stdout.starts_with(&format!("Diff in {} at line 1:\n-let x   =  1\n", path)),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn fmt_in_place() {
+    let dir = support::TempDir::new("fmt_in_place");
+    let path = &dir.write("fmt_in_place.glu", UNFORMATTED);
+    let output = Command::new(support::server_path())
+        .args(&["fmt", path])
+        .output()
+        .unwrap();
+    let check = Command::new(support::server_path())
+        .args(&["fmt", "--check", path])
+        .output()
+        .unwrap();
+    let mut source = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut source))
+        .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(source, format_expr(UNFORMATTED).unwrap());
+    assert!(check.status.success());
+    assert_eq!(str::from_utf8(&check.stdout).unwrap(), "");
+}
@@ -59,7 +59,7 @@ x + 2
             TextEdit {
                 range: Range {
                     start: Position {
-                        line: 0,
+                        line: 1,
                         character: 0,
                     },
                     end: Position {
@@ -67,7 +67,320 @@ x + 2
                         character: 0,
                     },
                 },
-                new_text: expected.to_string(),
+                new_text: expected[1..].to_string(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn range() {
+    let text = r#"
+let x =
+         1
+let y   =   2
+x   +
+   y
+"#;
+    let edits: Vec<TextEdit> = support::send_rpc(|mut stdin| {
+        support::did_open(stdin, "test", text);
+
+        let range_format = support::method_call(
+            "textDocument/rangeFormatting",
+            2,
+            DocumentRangeFormattingParams {
+                text_document: TextDocumentIdentifier { uri: support::test_url("test") },
+                range: Range {
+                    start: Position {
+                        line: 3,
+                        character: 5,
+                    },
+                    end: Position {
+                        line: 3,
+                        character: 5,
+                    },
+                },
+                options: FormattingOptions {
+                    tab_size: 4,
+                    insert_spaces: true,
+                    properties: Default::default(),
+                },
+            },
+        );
+        support::write_message(stdin, range_format).unwrap();
+    });
+
+    assert_eq!(
+        edits,
+        vec![
+            TextEdit {
+                range: Range {
+                    start: Position {
+                        line: 3,
+                        character: 0,
+                    },
+                    end: Position {
+                        line: 4,
+                        character: 0,
+                    },
+                },
+                new_text: "let y = 2\n".to_string(),
+            },
+        ]
+    );
+}
+
+fn on_type<W: ?Sized>(stdin: &mut W, id: u64, uri: &str, position: Position)
+where
+    W: Write,
+{
+    let on_type = support::method_call(
+        "textDocument/onTypeFormatting",
+        id,
+        DocumentOnTypeFormattingParams {
+            text_document: TextDocumentIdentifier { uri: support::test_url(uri) },
+            position: position,
+            ch: "\n".to_string(),
+            options: FormattingOptions {
+                tab_size: 4,
+                insert_spaces: true,
+                properties: Default::default(),
+            },
+        },
+    );
+
+    support::write_message(stdin, on_type).unwrap();
+}
+
+#[test]
+fn on_type_after_equals() {
+    let text = r#"
+let f x =
+x
+f 1
+"#;
+    let edits: Vec<TextEdit> = support::send_rpc(|mut stdin| {
+        support::did_open(stdin, "test", text);
+
+        on_type(
+            stdin,
+            2,
+            "test",
+            Position {
+                line: 2,
+                character: 0,
+            },
+        )
+    });
+
+    assert_eq!(
+        edits,
+        vec![
+            TextEdit {
+                range: Range {
+                    start: Position {
+                        line: 2,
+                        character: 0,
+                    },
+                    end: Position {
+                        line: 2,
+                        character: 0,
+                    },
+                },
+                new_text: "    ".to_string(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn on_type_after_in() {
+    let text = r#"
+    let x = 1 in
+        x
+"#;
+    let edits: Vec<TextEdit> = support::send_rpc(|mut stdin| {
+        support::did_open(stdin, "test", text);
+
+        on_type(
+            stdin,
+            2,
+            "test",
+            Position {
+                line: 2,
+                character: 8,
+            },
+        )
+    });
+
+    assert_eq!(
+        edits,
+        vec![
+            TextEdit {
+                range: Range {
+                    start: Position {
+                        line: 2,
+                        character: 0,
+                    },
+                    end: Position {
+                        line: 2,
+                        character: 8,
+                    },
+                },
+                new_text: "    ".to_string(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn only_changed_lines() {
+    let text = r#"
+let x = 1
+let y   =   2
+let z = 3
+x + y + z
+"#;
+    let edits: Vec<TextEdit> = support::send_rpc(|mut stdin| {
+        support::did_open(stdin, "test", text);
+
+        format(stdin, 2, "test")
+    });
+
+    assert_eq!(
+        edits,
+        vec![
+            TextEdit {
+                range: Range {
+                    start: Position {
+                        line: 2,
+                        character: 0,
+                    },
+                    end: Position {
+                        line: 3,
+                        character: 0,
+                    },
+                },
+                new_text: "let y = 2\n".to_string(),
+            },
+        ]
+    );
+}
+
+fn did_change<W: ?Sized>(stdin: &mut W, uri: &str, text: &str)
+where
+    W: Write,
+{
+    let did_change = support::notification(
+        "textDocument/didChange",
+        DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier {
+                uri: support::test_url(uri),
+                version: 2,
+            },
+            content_changes: vec![
+                TextDocumentContentChangeEvent {
+                    range: None,
+                    range_length: None,
+                    text: text.into(),
+                },
+            ],
+        },
+    );
+    support::write_message(stdin, did_change).unwrap();
+}
+
+/// Delays the checking of changed documents past the formatting requests in a test
+fn debounce<W: ?Sized>(stdin: &mut W)
+where
+    W: Write,
+{
+    let did_change_configuration = support::notification(
+        "workspace/didChangeConfiguration",
+        DidChangeConfigurationParams {
+            settings: serde_json::from_str(r#"{ "gluon": { "debounce": 2000 } }"#).unwrap(),
+        },
+    );
+    support::write_message(stdin, did_change_configuration).unwrap();
+}
+
+#[test]
+fn unchecked_change() {
+    let edits: Vec<TextEdit> = support::send_rpc(|mut stdin| {
+        debounce(stdin);
+        support::did_open(stdin, "test", "let x = 1\nx\n");
+        did_change(stdin, "test", "let x =   1\nx\n");
+
+        format(stdin, 2, "test")
+    });
+
+    assert_eq!(
+        edits,
+        vec![
+            TextEdit {
+                range: Range {
+                    start: Position {
+                        line: 0,
+                        character: 0,
+                    },
+                    end: Position {
+                        line: 1,
+                        character: 0,
+                    },
+                },
+                new_text: "let x = 1\n".to_string(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn unchecked_change_range() {
+    let edits: Vec<TextEdit> = support::send_rpc(|mut stdin| {
+        debounce(stdin);
+        support::did_open(stdin, "test", "let x = 1\nlet y = 2\nx + y\n");
+        did_change(stdin, "test", "let x = 1\nlet y   =   2\nx + y\n");
+
+        let range_format = support::method_call(
+            "textDocument/rangeFormatting",
+            2,
+            DocumentRangeFormattingParams {
+                text_document: TextDocumentIdentifier { uri: support::test_url("test") },
+                range: Range {
+                    start: Position {
+                        line: 1,
+                        character: 0,
+                    },
+                    end: Position {
+                        line: 1,
+                        character: 0,
+                    },
+                },
+                options: FormattingOptions {
+                    tab_size: 4,
+                    insert_spaces: true,
+                    properties: Default::default(),
+                },
+            },
+        );
+        support::write_message(stdin, range_format).unwrap();
+    });
+
+    assert_eq!(
+        edits,
+        vec![
+            TextEdit {
+                range: Range {
+                    start: Position {
+                        line: 1,
+                        character: 0,
+                    },
+                    end: Position {
+                        line: 2,
+                        character: 0,
+                    },
+                },
+                new_text: "let y = 2\n".to_string(),
             },
         ]
     );
@@ -0,0 +1,56 @@
+extern crate gluon_language_server;
+extern crate languageserver_types;
+
+extern crate jsonrpc_core;
+extern crate serde_json;
+extern crate serde;
+extern crate url;
+
+#[allow(unused)]
+mod support;
+
+use languageserver_types::{DidChangeTextDocumentParams, TextDocumentContentChangeEvent,
+                           VersionedTextDocumentIdentifier};
+
+#[test]
+fn recheck_closed_importer() {
+    let dir = support::TempDir::new("recheck_closed_importer");
+    let a = &dir.write("chain_a.glu", "{ value = 1 }\n");
+    let b = &dir.write(
+        "chain_b.glu",
+        &format!("let a = import! \"{}\"\n{{ value = a.value }}\n", a),
+    );
+
+    let c = support::test_url(&dir.path("chain_c.glu"));
+    let mut server = support::Server::spawn();
+    support::did_open(&mut server.stdin, a, "{ value = 1 }\n");
+    support::did_open(
+        &mut server.stdin,
+        &dir.path("chain_c.glu"),
+        &format!("let b = import! \"{}\"\nb.value + 1\n", b),
+    );
+    server.wait_for_diagnostics(&c, |diagnostics| diagnostics.is_empty());
+
+    // Only `a` is changed, `b` is neither open nor changed on disk
+    let did_change = support::notification(
+        "textDocument/didChange",
+        DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier {
+                uri: support::test_url(a),
+                version: 2,
+            },
+            content_changes: vec![
+                TextDocumentContentChangeEvent {
+                    range: None,
+                    range_length: None,
+                    text: "{ value = \"\" }\n".into(),
+                },
+            ],
+        },
+    );
+    support::write_message(&mut server.stdin, did_change).unwrap();
+    let diagnostics = server.wait_for_diagnostics(&c, |diagnostics| !diagnostics.is_empty());
+    server.exit();
+
+    assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+}
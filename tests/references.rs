@@ -0,0 +1,217 @@
+extern crate gluon_language_server;
+extern crate languageserver_types;
+
+extern crate jsonrpc_core;
+extern crate serde_json;
+extern crate serde;
+extern crate url;
+
+mod support;
+
+use std::io::Write;
+
+use url::Url;
+
+use languageserver_types::{Location, Position, Range, ReferenceContext, ReferenceParams,
+                           TextDocumentIdentifier};
+
+fn references<W: ?Sized>(
+    stdin: &mut W,
+    id: u64,
+    uri: &str,
+    position: Position,
+    include_declaration: bool,
+) where
+    W: Write,
+{
+    let references = support::method_call(
+        "textDocument/references",
+        id,
+        ReferenceParams {
+            text_document: TextDocumentIdentifier { uri: support::test_url(uri) },
+            position: position,
+            context: ReferenceContext { include_declaration: include_declaration },
+        },
+    );
+
+    support::write_message(stdin, references).unwrap();
+}
+
+fn location(uri: Url, line: u64, start: u64, end: u64) -> Location {
+    Location {
+        uri: uri,
+        range: Range {
+            start: Position {
+                line: line,
+                character: start,
+            },
+            end: Position {
+                line: line,
+                character: end,
+            },
+        },
+    }
+}
+
+#[test]
+fn local_references() {
+    let locations: Vec<Location> = support::send_rpc(|mut stdin| {
+        support::did_open(stdin, "test", "let x = 1\nlet y = x\nx");
+
+        references(
+            stdin,
+            2,
+            "test",
+            Position {
+                line: 0,
+                character: 4,
+            },
+            true,
+        );
+    });
+
+    let uri = support::test_url("test");
+    assert_eq!(
+        locations,
+        vec![
+            location(uri.clone(), 0, 4, 5),
+            location(uri.clone(), 1, 8, 9),
+            location(uri, 2, 0, 1),
+        ]
+    );
+}
+
+#[test]
+fn references_to_imported_field() {
+    let locations: Vec<Location> = support::send_rpc(|mut stdin| {
+        support::did_open(
+            stdin,
+            "test",
+            "let m = import! \"tests/module.glu\"\nlet { one } = m\nm.id one",
+        );
+
+        references(
+            stdin,
+            2,
+            "test",
+            Position {
+                line: 2,
+                character: 5,
+            },
+            false,
+        );
+    });
+
+    let uri = support::test_url("test");
+    assert_eq!(
+        locations,
+        vec![
+            location(uri.clone(), 1, 6, 9),
+            location(uri, 2, 5, 8),
+            location(support::test_url("tests/module.glu"), 2, 6, 9),
+        ]
+    );
+}
+
+#[test]
+fn references_from_defining_module() {
+    let locations: Vec<Location> = support::send_rpc(|mut stdin| {
+        support::did_open(
+            stdin,
+            "tests/module.glu",
+            "let id x = x\nlet one = 1\n{ id, one }\n",
+        );
+        support::did_open(
+            stdin,
+            "test",
+            "let { id } = import! \"tests/module.glu\"\nid 1",
+        );
+
+        references(
+            stdin,
+            3,
+            "tests/module.glu",
+            Position {
+                line: 0,
+                character: 4,
+            },
+            true,
+        );
+    });
+
+    let test_uri = support::test_url("test");
+    let module_uri = support::test_url("tests/module.glu");
+    assert_eq!(
+        locations,
+        vec![
+            location(test_uri.clone(), 0, 6, 8),
+            location(test_uri, 1, 0, 2),
+            location(module_uri.clone(), 0, 4, 6),
+            location(module_uri, 2, 2, 4),
+        ]
+    );
+}
+
+#[test]
+fn references_to_field_of_declared_type() {
+    let locations: Vec<Location> = support::send_rpc(|mut stdin| {
+        support::did_open(
+            stdin,
+            "test",
+            "type P = { px : Int }\nlet p : P = { px = 1 }\np.px",
+        );
+
+        references(
+            stdin,
+            2,
+            "test",
+            Position {
+                line: 2,
+                character: 3,
+            },
+            true,
+        );
+    });
+
+    let uri = support::test_url("test");
+    assert_eq!(
+        locations,
+        vec![
+            location(uri.clone(), 0, 11, 13),
+            location(uri.clone(), 1, 14, 16),
+            location(uri, 2, 2, 4),
+        ]
+    );
+}
+
+#[test]
+fn references_to_field_through_function_argument() {
+    let locations: Vec<Location> = support::send_rpc(|mut stdin| {
+        support::did_open(
+            stdin,
+            "test",
+            "type P = { px : Int }\nlet f r = r.px\nf { px = 1 }",
+        );
+
+        references(
+            stdin,
+            2,
+            "test",
+            Position {
+                line: 0,
+                character: 11,
+            },
+            true,
+        );
+    });
+
+    let uri = support::test_url("test");
+    assert_eq!(
+        locations,
+        vec![
+            location(uri.clone(), 0, 11, 13),
+            location(uri.clone(), 1, 12, 14),
+            location(uri, 2, 4, 6),
+        ]
+    );
+}
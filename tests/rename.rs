@@ -0,0 +1,352 @@
+extern crate gluon_language_server;
+extern crate languageserver_types;
+
+extern crate jsonrpc_core;
+extern crate serde_json;
+extern crate serde;
+extern crate url;
+
+mod support;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+
+use url::Url;
+
+use jsonrpc_core::{Error, ErrorCode, Output, Response};
+
+use languageserver_types::{Position, Range, RenameParams, TextDocumentIdentifier,
+                           TextDocumentPositionParams, TextEdit, WorkspaceEdit};
+
+fn rename<W: ?Sized>(stdin: &mut W, id: u64, uri: &str, position: Position, new_name: &str)
+where
+    W: Write,
+{
+    let rename = support::method_call(
+        "textDocument/rename",
+        id,
+        RenameParams {
+            text_document: TextDocumentIdentifier { uri: support::test_url(uri) },
+            position: position,
+            new_name: new_name.into(),
+        },
+    );
+
+    support::write_message(stdin, rename).unwrap();
+}
+
+fn range(line: u64, start: u64, end: u64) -> Range {
+    Range {
+        start: Position {
+            line: line,
+            character: start,
+        },
+        end: Position {
+            line: line,
+            character: end,
+        },
+    }
+}
+
+fn edit(line: u64, start: u64, end: u64, new_text: &str) -> TextEdit {
+    TextEdit {
+        range: range(line, start, end),
+        new_text: new_text.into(),
+    }
+}
+
+fn changes(changes: Vec<(Url, Vec<TextEdit>)>) -> WorkspaceEdit {
+    WorkspaceEdit { changes: changes.into_iter().collect::<HashMap<_, _>>() }
+}
+
+/// Returns the error which the server responded with when renaming the identifier at `position` in
+/// `text` to `new_name`
+fn rename_error(text: &str, position: Position, new_name: &str) -> Error {
+    let messages = support::server_messages(|mut stdin| {
+        support::did_open(stdin, "test", text);
+
+        rename(stdin, 2, "test", position, new_name);
+    });
+    messages
+        .iter()
+        .filter_map(|json| match serde_json::from_str(json) {
+            Ok(Response::Single(Output::Failure(failure))) => Some(failure.error),
+            _ => None,
+        })
+        .next()
+        .unwrap_or_else(|| panic!("Expected an error out of:\n`{}`", messages.join("\n")))
+}
+
+#[test]
+fn prepare_rename() {
+    let range_to_rename: Range = support::send_rpc(|mut stdin| {
+        support::did_open(stdin, "test", "let x = 1\nx");
+
+        let prepare_rename = support::method_call(
+            "textDocument/prepareRename",
+            2,
+            TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri: support::test_url("test") },
+                position: Position {
+                    line: 1,
+                    character: 0,
+                },
+            },
+        );
+        support::write_message(stdin, prepare_rename).unwrap();
+    });
+
+    assert_eq!(range_to_rename, range(1, 0, 1));
+}
+
+#[test]
+fn rename_local_binding() {
+    let edit_result: WorkspaceEdit = support::send_rpc(|mut stdin| {
+        support::did_open(stdin, "test", "let x = 1\nlet y = x\nx");
+
+        rename(
+            stdin,
+            2,
+            "test",
+            Position {
+                line: 2,
+                character: 0,
+            },
+            "z",
+        );
+    });
+
+    assert_eq!(
+        edit_result,
+        changes(vec![
+            (
+                support::test_url("test"),
+                vec![edit(0, 4, 5, "z"), edit(1, 8, 9, "z"), edit(2, 0, 1, "z")],
+            ),
+        ])
+    );
+}
+
+#[test]
+fn rename_binding_used_as_record_shorthand() {
+    let edit_result: WorkspaceEdit = support::send_rpc(|mut stdin| {
+        support::did_open(stdin, "test", "let x = 1\nlet r = { x }\nr.x");
+
+        rename(
+            stdin,
+            2,
+            "test",
+            Position {
+                line: 0,
+                character: 4,
+            },
+            "y",
+        );
+    });
+
+    assert_eq!(
+        edit_result,
+        changes(vec![
+            (
+                support::test_url("test"),
+                vec![edit(0, 4, 5, "y"), edit(1, 10, 11, "x = y")],
+            ),
+        ])
+    );
+}
+
+#[test]
+fn rename_type() {
+    let edit_result: WorkspaceEdit = support::send_rpc(|mut stdin| {
+        support::did_open(
+            stdin,
+            "test",
+            r#"type Shape = | Circle Int
+let area s : Shape -> Int = match s with | Circle r -> r
+area (Circle 1)"#,
+        );
+
+        rename(
+            stdin,
+            2,
+            "test",
+            Position {
+                line: 0,
+                character: 6,
+            },
+            "Figure",
+        );
+    });
+
+    assert_eq!(
+        edit_result,
+        changes(vec![
+            (
+                support::test_url("test"),
+                vec![edit(0, 5, 10, "Figure"), edit(1, 13, 18, "Figure")],
+            ),
+        ])
+    );
+}
+
+#[test]
+fn rename_exported_field() {
+    let edit_result: WorkspaceEdit = support::send_rpc(|mut stdin| {
+        support::did_open(
+            stdin,
+            "test",
+            "let m = import! \"tests/module.glu\"\nm.one",
+        );
+
+        rename(
+            stdin,
+            2,
+            "test",
+            Position {
+                line: 1,
+                character: 2,
+            },
+            "two",
+        );
+    });
+
+    let module_url = Url::from_file_path(fs::canonicalize("tests/module.glu").unwrap()).unwrap();
+    assert_eq!(
+        edit_result,
+        changes(vec![
+            (support::test_url("test"), vec![edit(1, 2, 5, "two")]),
+            (module_url, vec![edit(1, 4, 7, "two"), edit(2, 6, 9, "two")]),
+        ])
+    );
+}
+
+#[test]
+fn rename_to_keyword() {
+    let position = Position {
+        line: 1,
+        character: 0,
+    };
+    for keyword in &["in", "then"] {
+        let error = rename_error("let x = 1\nx", position, keyword);
+        assert_eq!(error.code, ErrorCode::InvalidParams, "{}", error.message);
+    }
+}
+
+#[test]
+fn rename_captured_by_inner_binding() {
+    let error = rename_error(
+        "let x = 1\nlet f y = x #Int+ y\nf 2",
+        Position {
+            line: 0,
+            character: 4,
+        },
+        "y",
+    );
+    assert_eq!(error.code, ErrorCode::InvalidParams, "{}", error.message);
+}
+
+#[test]
+fn rename_shadowing_outer_binding() {
+    let error = rename_error(
+        "let y = 1\nlet x = 2\ny",
+        Position {
+            line: 1,
+            character: 4,
+        },
+        "y",
+    );
+    assert_eq!(error.code, ErrorCode::InvalidParams, "{}", error.message);
+}
+
+#[test]
+fn rename_to_name_bound_in_other_scope() {
+    let edit_result: WorkspaceEdit = support::send_rpc(|mut stdin| {
+        support::did_open(stdin, "test", "let x = 1\nlet f y = y\nx");
+
+        rename(
+            stdin,
+            2,
+            "test",
+            Position {
+                line: 0,
+                character: 4,
+            },
+            "y",
+        );
+    });
+
+    assert_eq!(
+        edit_result,
+        changes(vec![
+            (
+                support::test_url("test"),
+                vec![edit(0, 4, 5, "y"), edit(2, 0, 1, "y")],
+            ),
+        ])
+    );
+}
+
+#[test]
+fn rename_field_of_declared_type() {
+    let edit_result: WorkspaceEdit = support::send_rpc(|mut stdin| {
+        support::did_open(
+            stdin,
+            "test",
+            "type P = { px : Int }\nlet p : P = { px = 1 }\np.px",
+        );
+
+        rename(
+            stdin,
+            2,
+            "test",
+            Position {
+                line: 2,
+                character: 3,
+            },
+            "qx",
+        );
+    });
+
+    assert_eq!(
+        edit_result,
+        changes(vec![
+            (
+                support::test_url("test"),
+                vec![edit(0, 11, 13, "qx"), edit(1, 14, 16, "qx"), edit(2, 2, 4, "qx")],
+            ),
+        ])
+    );
+}
+
+#[test]
+fn rename_field_of_declared_type_in_shorthand() {
+    let edit_result: WorkspaceEdit = support::send_rpc(|mut stdin| {
+        support::did_open(
+            stdin,
+            "test",
+            "type P = { px : Int }\nlet px = 1\nlet p : P = { px }\np.px",
+        );
+
+        rename(
+            stdin,
+            2,
+            "test",
+            Position {
+                line: 3,
+                character: 3,
+            },
+            "qx",
+        );
+    });
+
+    assert_eq!(
+        edit_result,
+        changes(vec![
+            (
+                support::test_url("test"),
+                vec![edit(0, 11, 13, "qx"), edit(2, 14, 16, "qx = px"), edit(3, 2, 4, "qx")],
+            ),
+        ])
+    );
+}
@@ -0,0 +1,191 @@
+extern crate gluon_language_server;
+extern crate languageserver_types;
+
+extern crate jsonrpc_core;
+#[macro_use]
+extern crate serde_json;
+extern crate serde;
+extern crate url;
+
+#[allow(unused)]
+mod support;
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use jsonrpc_core::{ErrorCode, Id, Output, Response};
+use url::Url;
+
+use languageserver_types::{DidChangeTextDocumentParams, DocumentFormattingParams,
+                           FormattingOptions, Position, TextDocumentContentChangeEvent,
+                           TextDocumentIdentifier, TextDocumentPositionParams, TextEdit,
+                           VersionedTextDocumentIdentifier};
+
+use gluon_language_server::rpc::REQUEST_CANCELLED;
+
+fn hover<W: ?Sized>(stdin: &mut W, id: u64, uri: Url, position: Position)
+where
+    W: Write,
+{
+    let hover = support::method_call(
+        "textDocument/hover",
+        id,
+        TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier { uri: uri },
+            position: position,
+        },
+    );
+
+    support::write_message(stdin, hover).unwrap();
+}
+
+fn format<W: ?Sized>(stdin: &mut W, id: u64, uri: Url)
+where
+    W: Write,
+{
+    let format = support::method_call(
+        "textDocument/formatting",
+        id,
+        DocumentFormattingParams {
+            text_document: TextDocumentIdentifier { uri: uri },
+            options: FormattingOptions {
+                tab_size: 4,
+                insert_spaces: true,
+                properties: Default::default(),
+            },
+        },
+    );
+
+    support::write_message(stdin, format).unwrap();
+}
+
+fn did_change<W: ?Sized>(stdin: &mut W, uri: Url, version: u64, text: &str)
+where
+    W: Write,
+{
+    let did_change = support::notification(
+        "textDocument/didChange",
+        DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier {
+                uri: uri,
+                version: version,
+            },
+            content_changes: vec![
+                TextDocumentContentChangeEvent {
+                    range: None,
+                    range_length: None,
+                    text: text.into(),
+                },
+            ],
+        },
+    );
+
+    support::write_message(stdin, did_change).unwrap();
+}
+
+/// Returns the responses which the server sent, by the id of the request they answer
+fn responses<F>(f: F) -> BTreeMap<u64, Output>
+where
+    F: FnOnce(&mut Write),
+{
+    let mut responses = BTreeMap::new();
+    for json in support::server_messages(f) {
+        if let Ok(Response::Single(output)) = serde_json::from_str(&json) {
+            let id = match output {
+                Output::Success(ref success) => success.id.clone(),
+                Output::Failure(ref failure) => failure.id.clone(),
+            };
+            match id {
+                Id::Num(id) => assert!(responses.insert(id, output).is_none()),
+                id => panic!("Unexpected id {:?}", id),
+            }
+        }
+    }
+    responses
+}
+
+const POSITION: Position = Position {
+    line: 1,
+    character: 0,
+};
+
+#[test]
+fn panicking_requests_are_answered() {
+    let responses = responses(|mut stdin| {
+        support::did_open(stdin, "test", "let x = 1\nx");
+
+        // More failing requests than there are threads handling requests
+        for id in 1..6 {
+            hover(stdin, id, Url::parse("untitled:Untitled-1").unwrap(), POSITION);
+        }
+        hover(stdin, 6, support::test_url("test"), POSITION);
+    });
+
+    for id in 1..6 {
+        match responses.get(&id) {
+            Some(&Output::Failure(ref failure)) => {
+                assert_eq!(failure.error.code, ErrorCode::InternalError)
+            }
+            output => panic!("Expected an internal error for {}: {:?}", id, output),
+        }
+    }
+    match responses.get(&6) {
+        Some(&Output::Success(_)) => (),
+        output => panic!("Expected a hover: {:?}", output),
+    }
+}
+
+#[test]
+fn cancelled_request_next_to_fast_request() {
+    let responses = responses(|mut stdin| {
+        support::did_open(stdin, "test", "let x = 1\nx");
+
+        hover(stdin, 1, support::test_url("test"), POSITION);
+        let cancel = support::notification("$/cancelRequest", json!({ "id": 1 }));
+        support::write_message(stdin, cancel).unwrap();
+        hover(stdin, 2, support::test_url("test"), POSITION);
+    });
+
+    assert_eq!(responses.keys().cloned().collect::<Vec<_>>(), vec![1, 2]);
+    // The request may have finished before the cancellation arrived
+    match responses[&1] {
+        Output::Success(_) => (),
+        Output::Failure(ref failure) => {
+            assert_eq!(failure.error.code, ErrorCode::ServerError(REQUEST_CANCELLED))
+        }
+    }
+    match responses[&2] {
+        Output::Success(_) => (),
+        ref output => panic!("Expected a hover: {:?}", output),
+    }
+}
+
+#[test]
+fn requests_use_the_text_from_when_they_were_sent() {
+    let text = |version: u64| format!("let x{} =  1\nx{}\n", version, version);
+    let responses = responses(|mut stdin| {
+        support::did_open(stdin, "test", &text(0));
+
+        // Each change is handled while the formatting request before it may still be running
+        for version in 1..30 {
+            format(stdin, version, support::test_url("test"));
+            did_change(stdin, support::test_url("test"), version, &text(version));
+        }
+    });
+
+    for version in 1..30 {
+        let edits: Vec<TextEdit> = match responses.get(&version) {
+            Some(&Output::Success(ref success)) => {
+                serde_json::from_value(success.result.clone()).unwrap()
+            }
+            output => panic!("Expected edits for {}: {:?}", version, output),
+        };
+        let expected = format!("let x{} = 1\n", version - 1);
+        assert!(
+            edits.iter().any(|edit| edit.new_text == expected),
+            "{}: {:?}",
+            version,
+            edits
+        );
+    }
+}
@@ -0,0 +1,141 @@
+extern crate gluon_language_server;
+extern crate languageserver_types;
+
+extern crate jsonrpc_core;
+#[macro_use]
+extern crate serde_json;
+extern crate serde;
+extern crate url;
+
+#[allow(unused)]
+mod support;
+
+use std::io::Write;
+
+use languageserver_types::{DidChangeConfigurationParams, DidChangeTextDocumentParams,
+                           PublishDiagnosticsParams, TextDocumentContentChangeEvent,
+                           VersionedTextDocumentIdentifier};
+use url::Url;
+
+const TWO_ERRORS: &'static str = r#"
+let x : Int = ""
+let y : String = 1
+x
+"#;
+
+fn did_change_configuration<W: ?Sized>(stdin: &mut W, settings: serde_json::Value)
+where
+    W: Write,
+{
+    let did_change_configuration = support::notification(
+        "workspace/didChangeConfiguration",
+        DidChangeConfigurationParams { settings: settings },
+    );
+    support::write_message(stdin, did_change_configuration).unwrap();
+}
+
+#[test]
+fn all_problems_by_default() {
+    let diagnostic: PublishDiagnosticsParams = support::send_rpc(|mut stdin| {
+        support::did_open(stdin, "test", TWO_ERRORS);
+    });
+    assert_eq!(diagnostic.diagnostics.len(), 2);
+}
+
+#[test]
+fn max_number_of_problems() {
+    let diagnostic: PublishDiagnosticsParams = support::send_rpc(|mut stdin| {
+        did_change_configuration(stdin, json!({ "gluon": { "maxNumberOfProblems": 1 } }));
+        support::did_open(stdin, "test", TWO_ERRORS);
+    });
+    assert_eq!(diagnostic.diagnostics.len(), 1);
+}
+
+#[test]
+fn max_number_of_problems_rechecks_open_documents() {
+    let mut server = support::Server::spawn();
+    let uri = support::test_url("test.glu");
+
+    support::did_open(&mut server.stdin, "test.glu", TWO_ERRORS);
+    server.wait_for_diagnostics(&uri, |diagnostics| diagnostics.len() == 2);
+
+    did_change_configuration(
+        &mut server.stdin,
+        json!({ "gluon": { "maxNumberOfProblems": 1 } }),
+    );
+    server.wait_for_diagnostics(&uri, |diagnostics| diagnostics.len() == 1);
+
+    server.exit();
+}
+
+#[test]
+fn import_paths() {
+    let mut server = support::Server::spawn();
+    let uri = support::test_url("test.glu");
+
+    support::did_open(&mut server.stdin, "test.glu", r#"import! "setting_module.glu""#);
+    server.wait_for_diagnostics(&uri, |diagnostics| !diagnostics.is_empty());
+
+    did_change_configuration(
+        &mut server.stdin,
+        json!({ "gluon": { "importPaths": ["tests/import_paths"] } }),
+    );
+    server.wait_for_diagnostics(&uri, |diagnostics| diagnostics.is_empty());
+
+    server.exit();
+}
+
+#[test]
+fn removing_import_paths_keeps_the_root() {
+    let mut server = support::Server::spawn();
+    let uri = support::test_url("test.glu");
+
+    did_change_configuration(&mut server.stdin, json!({ "gluon": { "importPaths": ["."] } }));
+    did_change_configuration(&mut server.stdin, json!({ "gluon": { "importPaths": [] } }));
+    support::did_open(&mut server.stdin, "test.glu", r#"import! "tests/module.glu""#);
+    let diagnostics = server.wait_for_diagnostics(&uri, |_| true);
+    assert_eq!(diagnostics, vec![]);
+
+    server.exit();
+}
+
+fn did_change<W: ?Sized>(stdin: &mut W, uri: &Url, text: &str)
+where
+    W: Write,
+{
+    let did_change = support::notification(
+        "textDocument/didChange",
+        DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier {
+                uri: uri.clone(),
+                version: 2,
+            },
+            content_changes: vec![
+                TextDocumentContentChangeEvent {
+                    range: None,
+                    range_length: None,
+                    text: text.into(),
+                },
+            ],
+        },
+    );
+    support::write_message(stdin, did_change).unwrap();
+}
+
+#[test]
+fn debounce() {
+    let mut server = support::Server::spawn();
+    let uri = support::test_url("test.glu");
+
+    did_change_configuration(&mut server.stdin, json!({ "gluon": { "debounce": 1000 } }));
+    support::did_open(&mut server.stdin, "test.glu", TWO_ERRORS);
+    server.wait_for_diagnostics(&uri, |diagnostics| diagnostics.len() == 2);
+
+    // The second change replaces the queued check of the first before it runs
+    did_change(&mut server.stdin, &uri, "let x : Int = \"\"\nx");
+    did_change(&mut server.stdin, &uri, "1");
+    let diagnostics = server.wait_for_diagnostics(&uri, |_| true);
+    assert_eq!(diagnostics, vec![]);
+
+    server.exit();
+}
@@ -0,0 +1,140 @@
+extern crate gluon_language_server;
+extern crate languageserver_types;
+
+extern crate jsonrpc_core;
+extern crate serde_json;
+extern crate serde;
+extern crate url;
+
+mod support;
+
+use std::io::Write;
+
+use languageserver_types::{ParameterInformation, Position, SignatureHelp, SignatureInformation,
+                           TextDocumentPositionParams, TextDocumentIdentifier};
+
+fn signature_help<W: ?Sized>(stdin: &mut W, id: u64, uri: &str, position: Position)
+where
+    W: Write,
+{
+    let signature_help = support::method_call(
+        "textDocument/signatureHelp",
+        id,
+        TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier { uri: support::test_url(uri) },
+            position: position,
+        },
+    );
+
+    support::write_message(stdin, signature_help).unwrap();
+}
+
+fn first_signature(active_parameter: u64) -> SignatureHelp {
+    SignatureHelp {
+        signatures: vec![
+            SignatureInformation {
+                label: "first : Int -> String -> Int".into(),
+                documentation: Some("Returns the first argument".into()),
+                parameters: Some(vec![
+                    ParameterInformation {
+                        label: "Int".into(),
+                        documentation: None,
+                    },
+                    ParameterInformation {
+                        label: "String".into(),
+                        documentation: None,
+                    },
+                ]),
+            },
+        ],
+        active_signature: Some(0),
+        active_parameter: Some(active_parameter),
+    }
+}
+
+const SOURCE: &'static str = r#"
+/// Returns the first argument
+let first x y : Int -> String -> Int = x
+first 1 "#;
+
+#[test]
+fn signature_help_after_argument() {
+    let help: SignatureHelp = support::send_rpc(|mut stdin| {
+        support::did_open(stdin, "test", SOURCE);
+
+        signature_help(
+            stdin,
+            2,
+            "test",
+            Position {
+                line: 3,
+                character: 8,
+            },
+        );
+    });
+
+    assert_eq!(help, first_signature(1));
+}
+
+#[test]
+fn signature_help_in_argument() {
+    let help: SignatureHelp = support::send_rpc(|mut stdin| {
+        support::did_open(stdin, "test", SOURCE);
+
+        signature_help(
+            stdin,
+            2,
+            "test",
+            Position {
+                line: 3,
+                character: 7,
+            },
+        );
+    });
+
+    assert_eq!(help, first_signature(0));
+}
+
+#[test]
+fn signature_help_at_trigger_characters() {
+    for &(trigger, character) in &[(" ", 6), (" (", 7)] {
+        let text = format!(
+            "\n/// Returns the first argument\nlet first x y : Int -> String -> Int = x\nfirst{}",
+            trigger
+        );
+        let help: SignatureHelp = support::send_rpc(|mut stdin| {
+            support::did_open(stdin, "test", &text);
+
+            signature_help(
+                stdin,
+                2,
+                "test",
+                Position {
+                    line: 3,
+                    character: character,
+                },
+            );
+        });
+
+        assert_eq!(help, first_signature(0), "After `first{}`", trigger);
+    }
+}
+
+#[test]
+fn no_signature_help_after_value() {
+    let help: SignatureHelp = support::send_rpc(|mut stdin| {
+        support::did_open(stdin, "test", "let x = 1\nx ");
+
+        signature_help(
+            stdin,
+            2,
+            "test",
+            Position {
+                line: 1,
+                character: 2,
+            },
+        );
+    });
+
+    assert!(help.signatures.is_empty());
+}
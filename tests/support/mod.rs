@@ -1,8 +1,12 @@
 use std::env;
-use std::io::{self, Write};
-use std::path::Path;
-use std::process::{Command, Stdio};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
 use std::str;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
 
 use jsonrpc_core::request::{Call, MethodCall, Notification};
 use jsonrpc_core::version::Version;
@@ -17,7 +21,8 @@ use serde_json::{Value, to_value, from_str, from_value};
 
 use url::Url;
 
-use languageserver_types::{DidOpenTextDocumentParams, TextDocumentItem};
+use languageserver_types::{Diagnostic, DidOpenTextDocumentParams, PublishDiagnosticsParams,
+                           TextDocumentItem};
 
 use gluon_language_server::rpc::read_message;
 
@@ -100,61 +105,224 @@ where
     did_open_uri(stdin, test_url(uri), text)
 }
 
-pub fn send_rpc<F, T>(f: F) -> T
-where
-    F: FnOnce(&mut Write),
-    T: DeserializeOwned,
-{
+pub fn server_path() -> PathBuf {
     let args: Vec<_> = env::args().collect();
-    let server_path = Path::new(&args[0][..])
+    Path::new(&args[0][..])
         .parent()
         .and_then(|p| p.parent())
         .expect("folder")
-        .join("gluon_language-server");
-    let mut child = Command::new(&*server_path)
+        .join("gluon_language-server")
+}
+
+fn spawn_server() -> Child {
+    let server_path = server_path();
+    Command::new(&*server_path)
         .arg("--quiet")
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .spawn()
         .unwrap_or_else(|err| {
             panic!("{}\nWhen opening `{}`", err, server_path.display())
-        });
+        })
+}
+
+fn exit<W: ?Sized>(stdin: &mut W)
+where
+    W: Write,
+{
+    let exit = Call::Notification(Notification {
+        jsonrpc: Some(Version::V2),
+        method: "exit".into(),
+        params: None,
+    });
+    write_message(stdin, exit).unwrap();
+}
+
+/// Runs the server with the messages which `f` writes and returns every message the server sent
+pub fn server_messages<F>(f: F) -> Vec<String>
+where
+    F: FnOnce(&mut Write),
+{
+    let mut child = spawn_server();
 
     {
         let mut stdin = child.stdin.as_mut().expect("stdin");
 
         f(stdin);
 
-        let exit = Call::Notification(Notification {
-            jsonrpc: Some(Version::V2),
-            method: "exit".into(),
-            params: None,
-        });
-        write_message(&mut stdin, exit).unwrap();
+        exit(stdin);
     }
 
     let result = child.wait_with_output().unwrap();
     assert!(result.status.success());
 
-    let mut value = None;
+    let mut messages = Vec::new();
     let mut output = &result.stdout[..];
     while let Some(json) = read_message(&mut output).unwrap() {
-        if let Ok(Response::Single(Output::Success(response))) = from_str(&json) {
-            value = from_value(response.result).ok();
+        messages.push(json);
+    }
+    messages
+}
+
+pub fn send_rpc<F, T>(f: F) -> T
+where
+    F: FnOnce(&mut Write),
+    T: DeserializeOwned,
+{
+    let messages = server_messages(f);
+
+    let mut value = None;
+    for json in &messages {
+        if let Ok(Response::Single(Output::Success(response))) = from_str(json) {
+            if let Ok(result) = from_value(response.result) {
+                value = Some(result);
+            }
         }
-        if let Ok(Notification { params: Some(params), .. }) = from_str(&json) {
+        if let Ok(Notification { params: Some(params), .. }) = from_str(json) {
             let json_value = match params {
                 Params::Map(map) => Value::Object(map),
                 Params::Array(array) => Value::Array(array),
                 Params::None => Value::Null,
             };
-            value = from_value(json_value).ok();
+            // Diagnostics may be published after the response that the test is looking for
+            if let Ok(params) = from_value(json_value) {
+                value = Some(params);
+            }
         }
     }
     value.unwrap_or_else(|| {
         panic!(
             "Could not find the expected response out of:\n`{}`",
-            str::from_utf8(&result.stdout).expect("UTF8")
+            messages.join("\n")
         )
     })
 }
+
+/// A server which a test can exchange messages with while it is running.
+///
+/// Work which is queued when the server exits is dropped, so tests of queued checks use this to
+/// wait for the results instead of `send_rpc`.
+#[allow(dead_code)]
+pub struct Server {
+    child: Child,
+    pub stdin: ChildStdin,
+    messages: mpsc::Receiver<String>,
+    received: Vec<String>,
+}
+
+#[allow(dead_code)]
+impl Server {
+    pub fn spawn() -> Server {
+        let mut child = spawn_server();
+
+        let stdout = child.stdout.take().expect("stdout");
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut stdout = BufReader::new(stdout);
+            while let Ok(Some(json)) = read_message(&mut stdout) {
+                if sender.send(json).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Server {
+            stdin: child.stdin.take().expect("stdin"),
+            child: child,
+            messages: receiver,
+            received: Vec::new(),
+        }
+    }
+
+    /// Waits for the diagnostics of `uri` to be published with diagnostics which `predicate`
+    /// accepts
+    pub fn wait_for_diagnostics<P>(&mut self, uri: &Url, predicate: P) -> Vec<Diagnostic>
+    where
+        P: Fn(&[Diagnostic]) -> bool,
+    {
+        while let Ok(json) = self.messages.recv_timeout(Duration::from_secs(30)) {
+            self.received.push(json.clone());
+            if let Ok(Notification { method, params: Some(Params::Map(map)), .. }) = from_str(&json)
+            {
+                if method != "textDocument/publishDiagnostics" {
+                    continue;
+                }
+                let params: PublishDiagnosticsParams = from_value(Value::Object(map)).unwrap();
+                if params.uri == *uri && predicate(&params.diagnostics) {
+                    return params.diagnostics;
+                }
+            }
+        }
+        panic!(
+            "Could not find the expected diagnostics for `{}` out of:\n`{}`",
+            uri,
+            self.received.join("\n")
+        )
+    }
+
+    /// Waits for the successful response to the request `id`
+    pub fn wait_for_response<T>(&mut self, id: u64) -> T
+    where
+        T: DeserializeOwned,
+    {
+        while let Ok(json) = self.messages.recv_timeout(Duration::from_secs(30)) {
+            self.received.push(json.clone());
+            if let Ok(Response::Single(Output::Success(response))) = from_str(&json) {
+                if response.id == Id::Num(id) {
+                    return from_value(response.result).unwrap();
+                }
+            }
+        }
+        panic!(
+            "Could not find the response to `{}` out of:\n`{}`",
+            id,
+            self.received.join("\n")
+        )
+    }
+
+    pub fn exit(mut self) {
+        exit(&mut self.stdin);
+        drop(self.stdin);
+        assert!(self.child.wait().unwrap().success());
+    }
+}
+
+/// A directory for the files which one test writes. It is relative to the working directory so
+/// that the files can be imported, and it is removed when the test ends, whether it passes or not.
+#[allow(dead_code)]
+pub struct TempDir {
+    path: PathBuf,
+}
+
+#[allow(dead_code)]
+impl TempDir {
+    /// Creates an empty directory for the test `name`
+    pub fn new(name: &str) -> TempDir {
+        let path = Path::new("target").join("tmp").join(name);
+        // A previous run which was killed may have left the directory behind
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir { path: path }
+    }
+
+    /// Returns the path of `file` in the directory
+    pub fn path(&self, file: &str) -> String {
+        self.path.join(file).to_str().expect("utf-8 path").to_string()
+    }
+
+    /// Writes `contents` to `file` in the directory and returns its path
+    pub fn write(&self, file: &str, contents: &str) -> String {
+        let path = self.path(file);
+        fs::create_dir_all(Path::new(&path).parent().expect("parent"))
+            .and_then(|_| File::create(&path))
+            .and_then(|mut file| file.write_all(contents.as_bytes()))
+            .unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
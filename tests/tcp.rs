@@ -0,0 +1,134 @@
+extern crate gluon_language_server;
+extern crate languageserver_types;
+
+extern crate jsonrpc_core;
+extern crate serde_json;
+extern crate serde;
+extern crate url;
+
+#[allow(unused)]
+mod support;
+
+use std::io::{BufRead, BufReader};
+use std::net::TcpStream;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use jsonrpc_core::request::Notification;
+use jsonrpc_core::params::Params;
+
+use languageserver_types::PublishDiagnosticsParams;
+
+use gluon_language_server::rpc::read_message;
+
+/// Starts a server which listens on `args` and returns it along with the line it printed
+fn spawn_listener(args: &[&str]) -> (Child, String) {
+    let mut child = Command::new(support::server_path())
+        .args(args)
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut line = String::new();
+    BufReader::new(child.stderr.as_mut().expect("stderr"))
+        .read_line(&mut line)
+        .unwrap();
+    (child, line)
+}
+
+fn spawn_tcp_server() -> (Child, u16) {
+    let (child, line) = spawn_listener(&["--port", "0"]);
+    let port = line.trim()
+        .split(' ')
+        .last()
+        .and_then(|port| port.parse::<u16>().ok())
+        .unwrap_or_else(|| panic!("Expected a port: `{}`", line));
+    (child, port)
+}
+
+fn read_diagnostics<R>(input: R) -> PublishDiagnosticsParams
+where
+    R: BufRead,
+{
+    let json = read_message(input).unwrap().expect("message");
+    let notification: Notification = serde_json::from_str(&json).unwrap();
+    assert_eq!(notification.method, "textDocument/publishDiagnostics");
+    match notification.params {
+        Some(Params::Map(map)) => serde_json::from_value(serde_json::Value::Object(map)).unwrap(),
+        params => panic!("Unexpected params {:?}", params),
+    }
+}
+
+#[test]
+fn diagnostics_over_tcp() {
+    let (mut child, port) = spawn_tcp_server();
+
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    support::did_open(&mut stream, "test", r#""" + 1"#);
+
+    let diagnostic = read_diagnostics(BufReader::new(&stream));
+    child.kill().unwrap();
+
+    assert_eq!(diagnostic.uri, support::test_url("test"));
+    assert_eq!(diagnostic.diagnostics.len(), 1);
+}
+
+#[test]
+fn clients_are_served_at_the_same_time() {
+    let (mut child, port) = spawn_tcp_server();
+
+    let first = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    let mut second = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    second
+        .set_read_timeout(Some(Duration::from_secs(30)))
+        .unwrap();
+    support::did_open(&mut second, "test", r#""" + 1"#);
+
+    let diagnostic = read_diagnostics(BufReader::new(&second));
+    drop(first);
+    child.kill().unwrap();
+
+    assert_eq!(diagnostic.diagnostics.len(), 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn socket_in_use() {
+    use std::fs;
+    use std::os::unix::net::UnixStream;
+
+    let path = "target/socket_in_use.sock";
+    let _ = fs::remove_file(path);
+    let (mut child, _) = spawn_listener(&["--socket", path]);
+
+    let mut second = Command::new(support::server_path())
+        .args(&["--socket", path])
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = second.try_wait().unwrap() {
+            break Some(status);
+        }
+        if start.elapsed() > Duration::from_secs(30) {
+            second.kill().unwrap();
+            break None;
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+    if status.map_or(true, |status| status.success()) {
+        child.kill().unwrap();
+        panic!("The second server should refuse to listen: {:?}", status);
+    }
+
+    // The running server still accepts clients
+    let mut stream = UnixStream::connect(path).unwrap();
+    support::did_open(&mut stream, "test", r#""" + 1"#);
+    let diagnostic = read_diagnostics(BufReader::new(&stream));
+    child.kill().unwrap();
+    let _ = fs::remove_file(path);
+
+    assert_eq!(diagnostic.diagnostics.len(), 1);
+}
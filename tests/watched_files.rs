@@ -0,0 +1,92 @@
+extern crate gluon_language_server;
+extern crate languageserver_types;
+
+extern crate jsonrpc_core;
+extern crate serde_json;
+extern crate serde;
+extern crate url;
+
+mod support;
+
+use std::fs;
+
+use languageserver_types::{DidChangeWatchedFilesParams, DocumentSymbolParams, FileChangeType,
+                           FileEvent, SymbolInformation, TextDocumentIdentifier};
+
+#[test]
+fn reload_changed_module() {
+    let dir = support::TempDir::new("reload_changed_module");
+    let path = &dir.write("watched.glu", "let before = 1\n{ before }\n");
+
+    let symbols: Vec<SymbolInformation> = support::send_rpc(|mut stdin| {
+        support::did_open(
+            stdin,
+            "test",
+            &format!("let watched = import! \"{}\"\nwatched.before", path),
+        );
+
+        dir.write("watched.glu", "let after = 1\n{ after }\n");
+        let did_change_watched_files = support::notification(
+            "workspace/didChangeWatchedFiles",
+            DidChangeWatchedFilesParams {
+                changes: vec![
+                    FileEvent {
+                        uri: support::test_url(path),
+                        typ: FileChangeType::Changed,
+                    },
+                ],
+            },
+        );
+        support::write_message(stdin, did_change_watched_files).unwrap();
+
+        let document_symbol = support::method_call(
+            "textDocument/documentSymbol",
+            2,
+            DocumentSymbolParams {
+                text_document: TextDocumentIdentifier { uri: support::test_url(path) },
+            },
+        );
+        support::write_message(stdin, document_symbol).unwrap();
+    });
+
+    let names: Vec<_> = symbols.iter().map(|symbol| &symbol.name[..]).collect();
+    assert!(names.contains(&"after"), "{:?}", names);
+    assert!(!names.contains(&"before"), "{:?}", names);
+}
+
+#[test]
+fn report_deleted_module() {
+    let dir = support::TempDir::new("report_deleted_module");
+    let path = &dir.write("deleted.glu", "{ value = 1 }\n");
+
+    let uri = support::test_url("test.glu");
+    let mut server = support::Server::spawn();
+    support::did_open(
+        &mut server.stdin,
+        "test.glu",
+        &format!("let deleted = import! \"{}\"\ndeleted.value\n", path),
+    );
+    server.wait_for_diagnostics(&uri, |diagnostics| diagnostics.is_empty());
+
+    fs::remove_file(path).unwrap();
+    let did_change_watched_files = support::notification(
+        "workspace/didChangeWatchedFiles",
+        DidChangeWatchedFilesParams {
+            changes: vec![
+                FileEvent {
+                    uri: support::test_url(path),
+                    typ: FileChangeType::Deleted,
+                },
+            ],
+        },
+    );
+    support::write_message(&mut server.stdin, did_change_watched_files).unwrap();
+    let diagnostics = server.wait_for_diagnostics(&uri, |diagnostics| !diagnostics.is_empty());
+    server.exit();
+
+    assert!(
+        diagnostics[0].message.contains(path),
+        "{:?}",
+        diagnostics
+    );
+}
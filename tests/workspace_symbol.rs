@@ -0,0 +1,169 @@
+extern crate gluon_language_server;
+extern crate languageserver_types;
+
+extern crate jsonrpc_core;
+extern crate serde_json;
+extern crate serde;
+extern crate url;
+
+mod support;
+
+use std::fs;
+use std::io::Write;
+
+use url::Url;
+
+use languageserver_types::{DidChangeWatchedFilesParams, FileChangeType, FileEvent, Location,
+                           Position, Range, SymbolInformation, SymbolKind, WorkspaceSymbolParams};
+
+fn workspace_symbol<W: ?Sized>(stdin: &mut W, id: u64, query: &str)
+where
+    W: Write,
+{
+    let workspace_symbol = support::method_call(
+        "workspace/symbol",
+        id,
+        WorkspaceSymbolParams { query: query.into() },
+    );
+
+    support::write_message(stdin, workspace_symbol).unwrap();
+}
+
+fn symbol(
+    name: &str,
+    kind: SymbolKind,
+    uri: Url,
+    line: u64,
+    start: u64,
+    end: u64,
+    container: &str,
+) -> SymbolInformation {
+    SymbolInformation {
+        name: name.into(),
+        kind: kind,
+        location: Location {
+            uri: uri,
+            range: Range {
+                start: Position {
+                    line: line,
+                    character: start,
+                },
+                end: Position {
+                    line: line,
+                    character: end,
+                },
+            },
+        },
+        container_name: Some(container.into()),
+    }
+}
+
+#[test]
+fn symbols_in_loaded_modules() {
+    let symbols: Vec<SymbolInformation> = support::send_rpc(|mut stdin| {
+        support::did_open(
+            stdin,
+            "test",
+            "let workspace_symbol_test = 1\nworkspace_symbol_test",
+        );
+
+        workspace_symbol(stdin, 2, "wrkspcsymtest");
+    });
+
+    assert_eq!(
+        symbols,
+        vec![
+            symbol(
+                "workspace_symbol_test",
+                SymbolKind::Variable,
+                support::test_url("test"),
+                0,
+                4,
+                29,
+                "test",
+            ),
+        ]
+    );
+}
+
+#[test]
+fn symbols_in_modules_on_disk() {
+    let symbols: Vec<SymbolInformation> = support::send_rpc(|mut stdin| {
+        support::did_open(stdin, "test", "1");
+
+        workspace_symbol(stdin, 2, "one");
+    });
+
+    let uri = Url::from_file_path(fs::canonicalize("tests/module.glu").unwrap()).unwrap();
+    assert_eq!(
+        symbols
+            .into_iter()
+            .filter(|symbol| symbol.container_name == Some("tests.module".into()))
+            .collect::<Vec<_>>(),
+        vec![
+            symbol("one", SymbolKind::Variable, uri.clone(), 1, 4, 11, "tests.module"),
+            symbol("one", SymbolKind::Field, uri, 2, 6, 9, "tests.module"),
+        ]
+    );
+}
+
+#[test]
+fn symbols_in_changed_modules_on_disk() {
+    let dir = support::TempDir::new("symbols_in_changed_modules_on_disk");
+    let changed = &dir.write("symbol_changed.glu", "let disk_before = 1\n{ disk_before }\n");
+    let created = &dir.path("symbol_created.glu");
+
+    let names = |symbols: Vec<SymbolInformation>| {
+        symbols
+            .into_iter()
+            .map(|symbol| symbol.name)
+            .collect::<Vec<_>>()
+    };
+
+    let mut server = support::Server::spawn();
+    support::did_open(&mut server.stdin, "test", "1");
+    workspace_symbol(&mut server.stdin, 2, "disk_");
+    let before = names(server.wait_for_response(2));
+
+    dir.write("symbol_changed.glu", "let disk_after = 1\n{ disk_after }\n");
+    dir.write("symbol_created.glu", "let disk_created = 1\n{ disk_created }\n");
+    let did_change_watched_files = support::notification(
+        "workspace/didChangeWatchedFiles",
+        DidChangeWatchedFilesParams {
+            changes: vec![
+                FileEvent {
+                    uri: support::test_url(changed),
+                    typ: FileChangeType::Changed,
+                },
+                FileEvent {
+                    uri: support::test_url(created),
+                    typ: FileChangeType::Created,
+                },
+            ],
+        },
+    );
+    support::write_message(&mut server.stdin, did_change_watched_files).unwrap();
+    workspace_symbol(&mut server.stdin, 3, "disk_");
+    let after = names(server.wait_for_response(3));
+    server.exit();
+
+    assert!(before.contains(&"disk_before".to_string()), "{:?}", before);
+    assert!(!after.contains(&"disk_before".to_string()), "{:?}", after);
+    assert!(after.contains(&"disk_after".to_string()), "{:?}", after);
+    assert!(after.contains(&"disk_created".to_string()), "{:?}", after);
+}
+
+#[test]
+fn no_symbols_from_modules_without_a_file() {
+    let symbols: Vec<SymbolInformation> = support::send_rpc(|mut stdin| {
+        support::did_open(stdin, "test", "1");
+
+        // `Ordering` is declared in `std.types`, which is embedded in the binary
+        workspace_symbol(stdin, 2, "Ordering");
+    });
+
+    for symbol in &symbols {
+        let path = symbol.location.uri.to_file_path().unwrap();
+        assert!(path.is_file(), "{:?}", symbol);
+    }
+}